@@ -0,0 +1,91 @@
+//! Certificate verification overhead for a known host, which is paid on every handshake.
+
+use cartouche_gemini::fingerprints::Fingerprint;
+use cartouche_gemini::known_hosts::KnownHosts;
+use cartouche_gemini::verify::{
+    CertificateDetails, CertificateTrustCache, CertificateVerifier, Endpoint, PinnedCertificate,
+    Response, ServerVerifier, TrustDecision, VerificationDelegate, VerificationIssue,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use rustls::Certificate;
+use std::fs;
+use url::Url;
+use x509_parser::certificate::X509Certificate;
+
+struct Reject;
+
+impl VerificationDelegate for Reject {
+    fn decide_certificate_trust(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _details: &CertificateDetails,
+        _endpoint: &Endpoint,
+        _issue: VerificationIssue,
+    ) -> TrustDecision {
+        TrustDecision::Abort
+    }
+}
+
+/// Known hosts which always parse certificates, as caches without the fast path do.
+struct Parsed(KnownHosts);
+
+impl CertificateTrustCache for Parsed {
+    fn get_certificate_trust(
+        &self,
+        certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    ) -> Response {
+        self.0
+            .get_certificate_trust(certificate, fingerprint, endpoint)
+    }
+
+    fn trust_certificate_once(
+        &self,
+        certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    ) {
+        self.0
+            .trust_certificate_once(certificate, fingerprint, endpoint)
+    }
+
+    fn trust_certificate_always(
+        &self,
+        certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    ) {
+        self.0
+            .trust_certificate_always(certificate, fingerprint, endpoint)
+    }
+}
+
+fn known_host(c: &mut Criterion) {
+    let certificate = rcgen::generate_simple_self_signed(vec!["example.com".to_owned()]).unwrap();
+    let certificate = Certificate(certificate.serialize_der().unwrap());
+    let (_, parsed) = x509_parser::parse_x509_certificate(&certificate.0).unwrap();
+    let pinned = PinnedCertificate::new(&parsed, &Fingerprint::new(&certificate));
+    let endpoint = Endpoint::from_url(&Url::parse("gemini://example.com/").unwrap()).unwrap();
+
+    let path = std::env::temp_dir().join(format!("cartouche-bench-{}", std::process::id()));
+    let known_hosts = || {
+        let known_hosts = KnownHosts::open(&path).unwrap();
+        known_hosts.trust_once(&endpoint.host, endpoint.port, &pinned);
+        known_hosts
+    };
+    let fast = CertificateVerifier::new(Reject, known_hosts());
+    let slow = CertificateVerifier::new(Reject, Parsed(known_hosts()));
+
+    let certificates = std::slice::from_ref(&certificate);
+    c.bench_function("known host", |b| {
+        b.iter(|| fast.verify_server_cert(certificates, &endpoint).unwrap())
+    });
+    c.bench_function("known host, parsed", |b| {
+        b.iter(|| slow.verify_server_cert(certificates, &endpoint).unwrap())
+    });
+    let _ = fs::remove_file(&path);
+}
+
+criterion_group!(benches, known_host);
+criterion_main!(benches);
@@ -1,12 +1,13 @@
 use cartouche_gemini::config;
-use cartouche_gemini::request::Request;
+use cartouche_gemini::fingerprints::Fingerprint;
+use cartouche_gemini::request::RequestBuilder;
 use cartouche_gemini::response::Error;
+use cartouche_gemini::text::TextDecoder;
 use cartouche_gemini::verify::{
-    CertificateTrustCache, CertificateVerifier, Response, TrustDecision, VerificationDelegate,
-    VerificationIssue,
+    CertificateDetails, CertificateTrustCache, CertificateVerifier, Endpoint, Response,
+    TrustDecision, VerificationDelegate, VerificationIssue,
 };
 use std::sync::Arc;
-use webpki::DNSNameRef;
 use x509_parser::certificate::X509Certificate;
 
 struct DummyCertificateTrustCache;
@@ -15,7 +16,8 @@ impl CertificateTrustCache for DummyCertificateTrustCache {
     fn get_certificate_trust(
         &self,
         _certificate: &X509Certificate<'_>,
-        _dns_name: DNSNameRef<'_>,
+        _fingerprint: &Fingerprint,
+        _endpoint: &Endpoint,
     ) -> Response {
         Response::UnknownCertificate
     }
@@ -23,14 +25,16 @@ impl CertificateTrustCache for DummyCertificateTrustCache {
     fn trust_certificate_once(
         &self,
         _certificate: &X509Certificate<'_>,
-        _dns_name: DNSNameRef<'_>,
+        _fingerprint: &Fingerprint,
+        _endpoint: &Endpoint,
     ) {
     }
 
     fn trust_certificate_always(
         &self,
         _certificate: &X509Certificate<'_>,
-        _dns_name: DNSNameRef<'_>,
+        _fingerprint: &Fingerprint,
+        _endpoint: &Endpoint,
     ) {
     }
 }
@@ -41,7 +45,8 @@ impl VerificationDelegate for DummyVerificationDelegate {
     fn decide_certificate_trust(
         &self,
         _certificate: &X509Certificate<'_>,
-        _dns_name: DNSNameRef<'_>,
+        _details: &CertificateDetails,
+        _endpoint: &Endpoint,
         _issue: VerificationIssue,
     ) -> TrustDecision {
         TrustDecision::TrustTemporary
@@ -50,28 +55,47 @@ impl VerificationDelegate for DummyVerificationDelegate {
 
 fn main() {
     let verifier = CertificateVerifier::new(DummyVerificationDelegate, DummyCertificateTrustCache);
-    let config = config::new_shared_config(Arc::new(verifier));
+    let config = config::new_shared_config(Arc::new(verifier), None);
 
-    // URL without a terminal slash results in a permanent redirect which is not supported yet.
-    let mut response = Request::perform("gemini://gemini.circumlunar.space/", &config).expect("request");
+    // URL without a terminal slash results in a permanent redirect which is followed.
+    let response = RequestBuilder::new("gemini://gemini.circumlunar.space")
+        .expect("valid URL")
+        .max_body_size(1024 * 1024)
+        .perform(&config)
+        .expect("request");
 
+    for redirect in response.redirects() {
+        println!("[>] redirected: {} -> {}", redirect.from, redirect.to);
+    }
+    println!("[+] header: {:?}", response.header());
+
+    let mut decoder = TextDecoder::new(response);
     loop {
-        let mut buffer = vec![0; 4096];
-        match response.read(&mut buffer) {
-            Ok(0) => {
-                println!("[!] EOF");
+        match decoder.read_chunk() {
+            Ok(Some(string)) => {
+                println!("[R] received: {}", string);
+            }
+            Ok(None) => {
+                println!("[+] terminating connection");
                 break;
             }
-            Ok(read) => {
-                buffer.resize(read, 0);
-                let string = String::from_utf8(buffer).expect("valid UTF-8");
-                println!("[R] received: {}", string);
+            Err(Error::Interrupted) | Err(Error::Terminated) => {
+                unreachable!("handled by decoder");
             }
-            Err(Error::Interrupted) => {
-                continue;
+            Err(Error::Truncated) => {
+                println!("[!] response truncated");
+                break;
             }
-            Err(Error::Terminated) => {
-                println!("[+] terminating connection");
+            Err(Error::Cancelled) => {
+                println!("[!] cancelled");
+                break;
+            }
+            Err(Error::FirstByteTimeout) | Err(Error::ReadTimeout) => {
+                println!("[!] response timed out");
+                break;
+            }
+            Err(Error::BodyTooLarge(limit)) => {
+                println!("[!] response is larger than {} bytes", limit);
                 break;
             }
             Err(Error::Protocol(err)) => {
@@ -88,4 +112,15 @@ fn main() {
             }
         }
     }
+
+    let report = decoder.report();
+    if let Some(charset) = &report.unknown_charset {
+        println!(
+            "[!] unknown charset {:?}, decoded as {}",
+            charset, report.encoding
+        );
+    }
+    if report.had_replacements {
+        println!("[!] malformed text replaced");
+    }
 }
@@ -0,0 +1,116 @@
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Handle for cancelling requests from another thread.
+///
+/// Clones of the token refer to the same cancellation state.
+/// Once cancelled, ongoing operations fail with a "cancelled" error as soon as possible,
+/// including the ones blocked on the network.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    callbacks: Mutex<Callbacks>,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    next_id: u64,
+    pending: Vec<(u64, Box<dyn FnOnce() + Send>)>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels all operations using this token. Cancellation cannot be undone.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let callbacks = mem::take(&mut self.inner.callbacks.lock().unwrap().pending);
+        for (_, callback) in callbacks {
+            callback();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Calls `callback` when the token is cancelled, unless the returned guard is dropped first.
+    ///
+    /// If the token is already cancelled, `callback` is called immediately.
+    pub(crate) fn on_cancel(&self, callback: impl FnOnce() + Send + 'static) -> CancelGuard {
+        let mut callbacks = self.inner.callbacks.lock().unwrap();
+        if self.is_cancelled() {
+            drop(callbacks);
+            callback();
+            return CancelGuard {
+                inner: self.inner.clone(),
+                id: None,
+            };
+        }
+        let id = callbacks.next_id;
+        callbacks.next_id += 1;
+        callbacks.pending.push((id, Box::new(callback)));
+        CancelGuard {
+            inner: self.inner.clone(),
+            id: Some(id),
+        }
+    }
+}
+
+/// Unregisters cancellation callback when dropped.
+pub(crate) struct CancelGuard {
+    inner: Arc<Inner>,
+    id: Option<u64>,
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut callbacks = self.inner.callbacks.lock().unwrap();
+            callbacks.pending.retain(|(other, _)| *other != id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn callbacks() {
+        let token = CancelToken::new();
+        let called = Arc::new(AtomicUsize::new(0));
+
+        let counter = called.clone();
+        let _kept = token.on_cancel(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let counter = called.clone();
+        let dropped = token.on_cancel(move || {
+            counter.fetch_add(10, Ordering::SeqCst);
+        });
+        drop(dropped);
+
+        token.clone().cancel();
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(called.load(Ordering::SeqCst), 1);
+
+        let counter = called.clone();
+        let _late = token.on_cancel(move || {
+            counter.fetch_add(100, Ordering::SeqCst);
+        });
+        assert_eq!(called.load(Ordering::SeqCst), 101);
+    }
+}
@@ -1,8 +1,190 @@
-use rustls::{ClientConfig, ServerCertVerifier};
+use crate::host::Host;
+use crate::identity::{Identity, IdentityResolver};
+use crate::verify::{Endpoint, ServerVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    Certificate, ClientConfig, NoClientSessionStorage, ResolvesClientCert, RootCertStore,
+    ServerCertVerified, ServerCertVerifier, SignatureScheme, StoresClientSessions, TLSError,
+};
 use std::sync::Arc;
+use url::Url;
+use webpki::DNSNameRef;
 
-pub fn new_shared_config(verifier: Arc<dyn ServerCertVerifier>) -> Arc<ClientConfig> {
-    let mut config = ClientConfig::new();
-    config.dangerous().set_certificate_verifier(verifier);
-    Arc::new(config)
+/// TLS configuration shared by requests.
+///
+/// rustls sessions get a copy of it with the verifier bound to the endpoint they connect to.
+pub struct Config {
+    tls: ClientConfig,
+    /// Replaces the verifier of `tls`, unless the configuration is taken from rustls as is.
+    verifier: Option<Arc<dyn ServerVerifier>>,
+    identities: Option<Arc<dyn IdentityResolver>>,
+}
+
+/// Creates configuration with given server verifier.
+///
+/// Client certificates are presented for requests scoped to an identity,
+/// or if `identities` resolver provides one for the requested URL.
+pub fn new_shared_config(
+    verifier: Arc<dyn ServerVerifier>,
+    identities: Option<Arc<dyn IdentityResolver>>,
+) -> Arc<Config> {
+    Arc::new(Config::new(verifier, identities))
+}
+
+impl Config {
+    pub fn new(
+        verifier: Arc<dyn ServerVerifier>,
+        identities: Option<Arc<dyn IdentityResolver>>,
+    ) -> Self {
+        Self {
+            tls: ClientConfig::new(),
+            verifier: Some(verifier),
+            identities,
+        }
+    }
+
+    /// Identity to present for `url`, unless the request is scoped to some other one.
+    pub(crate) fn resolve_identity(&self, url: &Url) -> Option<Arc<Identity>> {
+        self.identities
+            .as_ref()
+            .and_then(|identities| identities.resolve_identity(url))
+    }
+
+    /// Configuration for a session with `endpoint`, presenting `identity` if requested.
+    pub(crate) fn session_config(
+        &self,
+        endpoint: &Endpoint,
+        identity: Option<&Identity>,
+    ) -> Result<Arc<ClientConfig>, TLSError> {
+        let mut config = self.tls.clone();
+        if let Some(verifier) = &self.verifier {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(SessionVerifier {
+                    verifier: verifier.clone(),
+                    endpoint: endpoint.clone(),
+                }));
+        }
+        // Certificates are trusted for a particular port, and resumed sessions are not verified.
+        config.session_persistence = Arc::new(PortSessions {
+            sessions: self.tls.session_persistence.clone(),
+            port: endpoint.port,
+        });
+        if let Host::Ip(_) = endpoint.host {
+            // SNI must not contain IP addresses, and sessions are resumed by name.
+            config.enable_sni = false;
+            config.session_persistence = Arc::new(NoClientSessionStorage {});
+        }
+        if let Some(identity) = identity {
+            // Share the signing key instead of copying the private key into each session.
+            config.client_auth_cert_resolver = Arc::new(PresentIdentity(identity.certified_key()?));
+            // Resumed sessions keep the identity they have been established with.
+            // Do not share them with requests which are not scoped to the same identity.
+            config.session_persistence = Arc::new(NoClientSessionStorage {});
+        }
+        Ok(Arc::new(config))
+    }
+}
+
+/// Uses rustls configuration as is, verifying servers with its own certificate verifier.
+///
+/// That's how requests used to be configured. The verifier is not told about the port,
+/// and is given no name to verify IP addresses against.
+impl From<ClientConfig> for Config {
+    fn from(tls: ClientConfig) -> Self {
+        Self {
+            tls,
+            verifier: None,
+            identities: None,
+        }
+    }
+}
+
+/// Types which can be used as request configuration.
+pub trait IntoConfig {
+    fn into_config(self) -> Arc<Config>;
+}
+
+impl IntoConfig for Arc<Config> {
+    fn into_config(self) -> Arc<Config> {
+        self
+    }
+}
+
+impl IntoConfig for &Arc<Config> {
+    fn into_config(self) -> Arc<Config> {
+        self.clone()
+    }
+}
+
+impl IntoConfig for Arc<ClientConfig> {
+    fn into_config(self) -> Arc<Config> {
+        (&self).into_config()
+    }
+}
+
+impl IntoConfig for &Arc<ClientConfig> {
+    fn into_config(self) -> Arc<Config> {
+        Arc::new(Config::from(ClientConfig::clone(self)))
+    }
+}
+
+/// Presents the same identity to any server which asks for it.
+struct PresentIdentity(CertifiedKey);
+
+impl ResolvesClientCert for PresentIdentity {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<CertifiedKey> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Keeps sessions with different ports of a host apart. rustls resumes them by host name.
+struct PortSessions {
+    sessions: Arc<dyn StoresClientSessions>,
+    port: u16,
+}
+
+impl PortSessions {
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        let mut key = key.to_vec();
+        key.extend_from_slice(&self.port.to_be_bytes());
+        key
+    }
+}
+
+impl StoresClientSessions for PortSessions {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.sessions.put(self.key(&key), value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.sessions.get(&self.key(key))
+    }
+}
+
+/// Passes the actual endpoint to the verifier, instead of the name rustls knows.
+struct SessionVerifier {
+    verifier: Arc<dyn ServerVerifier>,
+    endpoint: Endpoint,
+}
+
+impl ServerCertVerifier for SessionVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        self.verifier
+            .verify_server_cert(presented_certs, &self.endpoint)
+    }
 }
@@ -0,0 +1,78 @@
+//! Bare minimum of DER for digging into certificates and keys.
+
+use zeroize::Zeroizing;
+
+/// Splits the first DER element off the input.
+pub(crate) fn element(der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (header, length) = header(der)?;
+    let end = header.checked_add(length)?;
+    if der.len() < end {
+        return None;
+    }
+    Some(der.split_at(end))
+}
+
+/// Contents of a DER element, without its tag and length.
+pub(crate) fn contents(element: &[u8]) -> Option<&[u8]> {
+    let (header, _) = header(element)?;
+    element.get(header..)
+}
+
+/// Length of the tag and length octets, and the length of contents.
+fn header(der: &[u8]) -> Option<(usize, usize)> {
+    let first = *der.get(1)?;
+    if first & 0x80 == 0 {
+        return Some((2, first as usize));
+    }
+    let count = (first & 0x7F) as usize;
+    if count == 0 || count > 4 {
+        return None;
+    }
+    let length = der
+        .get(2..2 + count)?
+        .iter()
+        .fold(0, |length, byte| length << 8 | *byte as usize);
+    Some((2 + count, length))
+}
+
+/// Encodes an element with given tag and concatenation of `contents`.
+///
+/// The result is zeroized, since it may well be a private key.
+pub(crate) fn encode(tag: u8, contents: &[&[u8]]) -> Zeroizing<Vec<u8>> {
+    let length = contents.iter().map(|part| part.len()).sum::<usize>();
+    let length_octets = length.to_be_bytes();
+    let significant = length_octets.iter().skip_while(|byte| **byte == 0).count();
+    let header = if length < 0x80 { 2 } else { 2 + significant };
+    let mut der = Zeroizing::new(Vec::with_capacity(header + length));
+    der.push(tag);
+    if length < 0x80 {
+        der.push(length as u8);
+    } else {
+        der.push(0x80 | significant as u8);
+        der.extend_from_slice(&length_octets[length_octets.len() - significant..]);
+    }
+    for part in contents {
+        der.extend_from_slice(part);
+    }
+    der
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        for length in &[0, 0x7F, 0x80, 0x1234] {
+            let contents = vec![0xAB; *length];
+            let der = encode(
+                0x04,
+                &[&contents[..1.min(*length)], &contents[1.min(*length)..]],
+            );
+            assert_eq!(der.capacity(), der.len());
+            let (element, rest) = element(&der).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(super::contents(element).unwrap(), &contents[..]);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use sha2::{Digest, Sha256};
 use std::fmt;
+use std::str::FromStr;
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Fingerprint {
     SHA256(Vec<u8>),
 }
@@ -9,6 +11,13 @@ impl Fingerprint {
     pub fn new(data: impl AsRef<[u8]>) -> Self {
         Self::SHA256(Sha256::digest(data.as_ref()).to_vec())
     }
+
+    /// Digest value, without the algorithm.
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Fingerprint::SHA256(bytes) => bytes,
+        }
+    }
 }
 
 impl fmt::Display for Fingerprint {
@@ -24,11 +33,37 @@ impl fmt::Display for Fingerprint {
     }
 }
 
+/// Error parsing `Fingerprint` from its text form.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid fingerprint")]
+pub struct ParseFingerprintError;
+
+impl FromStr for Fingerprint {
+    type Err = ParseFingerprintError;
+
+    /// Parses fingerprint in the same format as it is displayed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut components = s.split(':');
+        let tag = components.next().ok_or(ParseFingerprintError)?;
+        let bytes = components
+            .map(|byte| match byte.len() {
+                2 => u8::from_str_radix(byte, 16).map_err(|_| ParseFingerprintError),
+                _ => Err(ParseFingerprintError),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match tag {
+            "SHA-256" if bytes.len() == 32 => Ok(Fingerprint::SHA256(bytes)),
+            _ => Err(ParseFingerprintError),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn default_fingerprint() {
         let fingerprint = Fingerprint::new(&[]);
         assert_eq!(
@@ -36,4 +71,26 @@ mod tests {
             "SHA-256:E3:B0:C4:42:98:FC:1C:14:9A:FB:F4:C8:99:6F:B9:24:27:AE:41:E4:64:9B:93:4C:A4:95:99:1B:78:52:B8:55",
         );
     }
+
+    #[test]
+    fn parse_fingerprint() {
+        let fingerprint = Fingerprint::new(b"");
+        let text = fingerprint.to_string();
+        assert_eq!(text.parse::<Fingerprint>().unwrap(), fingerprint);
+
+        let bytes = &text["SHA-256".len()..];
+        let invalid = [
+            "SHA-256:E3:B0".to_owned(),
+            format!("{}:00", text),
+            text[..text.len() - 3].to_owned(),
+            text.replacen("E3", "G3", 1),
+            text.replacen("E3", "E", 1),
+            format!("MD5{}", bytes),
+            bytes.to_owned(),
+            bytes[1..].to_owned(),
+        ];
+        for text in &invalid {
+            assert!(text.parse::<Fingerprint>().is_err(), "{}", text);
+        }
+    }
 }
@@ -0,0 +1,267 @@
+use crate::media_type::MediaType;
+use crate::redirect::RedirectKind;
+use crate::response::ProtocolError;
+use crate::status::{Category, Status};
+use std::convert::TryInto;
+use std::str;
+use url::Url;
+
+/// Response header, interpreted according to its status.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResponseHeader {
+    /// 1x: prompt to display to the user.
+    Input { status: Status, prompt: String },
+    /// 2x: MIME type of the body.
+    Success {
+        status: Status,
+        media_type: MediaType,
+    },
+    /// 3x: new location of the resource, resolved against the request URL.
+    Redirect { status: Status, target: Url },
+    /// 4x: error message for the user.
+    TemporaryFailure { status: Status, message: String },
+    /// 5x: error message for the user.
+    PermanentFailure { status: Status, message: String },
+    /// 6x: error message for the user.
+    ClientCertificate { status: Status, message: String },
+}
+
+impl ResponseHeader {
+    /// Parses header line (without the line ending) of a response to `url`.
+    pub(crate) fn parse(header: &[u8], url: &Url) -> Result<Self, ProtocolError> {
+        let (status, meta) = parse_header(header)?;
+        let meta = meta.to_owned();
+        Ok(match status.category() {
+            Category::Input => ResponseHeader::Input {
+                status,
+                prompt: meta,
+            },
+            Category::Success => ResponseHeader::Success {
+                status,
+                media_type: MediaType::parse(&meta)?,
+            },
+            Category::Redirect => {
+                let target = url
+                    .join(meta.trim())
+                    .map_err(ProtocolError::InvalidRedirect)?;
+                ResponseHeader::Redirect { status, target }
+            }
+            Category::TemporaryFailure => ResponseHeader::TemporaryFailure {
+                status,
+                message: meta,
+            },
+            Category::PermanentFailure => ResponseHeader::PermanentFailure {
+                status,
+                message: meta,
+            },
+            Category::ClientCertificate => ResponseHeader::ClientCertificate {
+                status,
+                message: meta,
+            },
+        })
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ResponseHeader::Input { status, .. }
+            | ResponseHeader::Success { status, .. }
+            | ResponseHeader::Redirect { status, .. }
+            | ResponseHeader::TemporaryFailure { status, .. }
+            | ResponseHeader::PermanentFailure { status, .. }
+            | ResponseHeader::ClientCertificate { status, .. } => *status,
+        }
+    }
+
+    /// Kind and target of a redirect, if this is one.
+    pub fn redirect(&self) -> Option<(RedirectKind, &Url)> {
+        match self {
+            ResponseHeader::Redirect { status, target } => {
+                let kind = if *status == Status::PermanentRedirect {
+                    RedirectKind::Permanent
+                } else {
+                    RedirectKind::Temporary
+                };
+                Some((kind, target))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Maximum length of the header line: two-digit status, space, 1024 bytes of META, CRLF.
+const MAX_HEADER_LENGTH: usize = 2 + 1 + 1024 + 2;
+
+/// Accumulates response header while it is being read from the network.
+pub(crate) struct HeaderBuffer {
+    buffer: Vec<u8>,
+    filled: usize,
+    line_ending: Option<usize>,
+}
+
+impl HeaderBuffer {
+    pub(crate) fn new() -> Self {
+        Self {
+            buffer: vec![0; MAX_HEADER_LENGTH],
+            filled: 0,
+            line_ending: None,
+        }
+    }
+
+    /// Space for the next read.
+    pub(crate) fn unfilled(&mut self) -> Result<&mut [u8], ProtocolError> {
+        if self.filled == self.buffer.len() {
+            return Err(ProtocolError::HeaderTooLong);
+        }
+        Ok(&mut self.buffer[self.filled..])
+    }
+
+    /// Accounts for `read` more bytes. Returns true once the header line is complete.
+    pub(crate) fn advance(&mut self, read: usize) -> bool {
+        // Compare one byte before, just in case the last one of the previous buffer
+        // was \r and we're reading in \n that follows it just now.
+        let before = self.filled.saturating_sub(1);
+        self.filled += read;
+        if let Some(index) = line_ending(&self.buffer[before..self.filled]) {
+            self.line_ending = Some(before + index);
+        }
+        self.line_ending.is_some()
+    }
+
+    /// Parses complete header of a response to `url`, returning it with the beginning of the body.
+    pub(crate) fn parse(mut self, url: &Url) -> Result<(ResponseHeader, Vec<u8>), ProtocolError> {
+        let line_ending = self.line_ending.expect("header must be complete");
+        self.buffer.truncate(self.filled);
+        let body = self.buffer.split_off(line_ending + 2);
+        let header = ResponseHeader::parse(&self.buffer[..line_ending], url)?;
+        Ok((header, body))
+    }
+}
+
+/// Finds the position of CRLF in the slice.
+fn line_ending(slice: &[u8]) -> Option<usize> {
+    slice.windows(2).position(|bytes| bytes == b"\r\n")
+}
+
+fn parse_header(header: &[u8]) -> Result<(Status, &str), ProtocolError> {
+    if header.len() < 2 {
+        return Err(ProtocolError::HeaderTooShort);
+    }
+    if header.len() > 2 && header[2] != b' ' {
+        return Err(ProtocolError::HeaderMalformed);
+    }
+    let status = &header[0..2];
+    let meta = if header.len() > 2 { &header[3..] } else { b"" };
+    let status = str::from_utf8(status)
+        .map_err(|_| ProtocolError::HeaderMalformed)?
+        .parse::<u8>()
+        .map_err(|_| ProtocolError::HeaderMalformed)?
+        .try_into()
+        .map_err(|_| ProtocolError::UnknownStatus)?;
+    let meta = str::from_utf8(meta).map_err(|_| ProtocolError::HeaderMalformed)?;
+    Ok((status, meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &str) -> Result<ResponseHeader, ProtocolError> {
+        let url = Url::parse("gemini://example.com/dir/page").unwrap();
+        ResponseHeader::parse(header.as_bytes(), &url)
+    }
+
+    #[test]
+    fn every_status_family() {
+        assert_eq!(
+            parse("11 Password").unwrap(),
+            ResponseHeader::Input {
+                status: Status::SensitiveInput,
+                prompt: "Password".to_owned(),
+            }
+        );
+        assert_eq!(
+            parse("20 text/gemini; charset=utf-8").unwrap(),
+            ResponseHeader::Success {
+                status: Status::Success,
+                media_type: MediaType::default(),
+            }
+        );
+        assert_eq!(
+            parse("31 other").unwrap(),
+            ResponseHeader::Redirect {
+                status: Status::PermanentRedirect,
+                target: Url::parse("gemini://example.com/dir/other").unwrap(),
+            }
+        );
+        assert_eq!(
+            parse("44 10").unwrap(),
+            ResponseHeader::TemporaryFailure {
+                status: Status::SlowDown,
+                message: "10".to_owned(),
+            }
+        );
+        assert_eq!(
+            parse("51 Not found").unwrap(),
+            ResponseHeader::PermanentFailure {
+                status: Status::NotFound,
+                message: "Not found".to_owned(),
+            }
+        );
+        assert_eq!(
+            parse("60").unwrap(),
+            ResponseHeader::ClientCertificate {
+                status: Status::ClientCertRequired,
+                message: String::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_status_codes() {
+        assert_eq!(
+            parse("21 text/plain").unwrap(),
+            ResponseHeader::Success {
+                status: Status::Other {
+                    code: 21,
+                    category: Category::Success,
+                },
+                media_type: MediaType::parse("text/plain").unwrap(),
+            }
+        );
+        assert!(matches!(
+            parse("71 what"),
+            Err(ProtocolError::UnknownStatus)
+        ));
+    }
+
+    #[test]
+    fn header_split_between_reads() {
+        let mut buffer = HeaderBuffer::new();
+        for (chunk, complete) in &[(&b"20 text/gemini\r"[..], false), (b"\n# Hi", true)] {
+            let unfilled = buffer.unfilled().unwrap();
+            unfilled[..chunk.len()].copy_from_slice(chunk);
+            assert_eq!(buffer.advance(chunk.len()), *complete);
+        }
+        let url = Url::parse("gemini://example.com/").unwrap();
+        let (header, body) = buffer.parse(&url).unwrap();
+        assert_eq!(header.status(), Status::Success);
+        assert_eq!(body, b"# Hi");
+    }
+
+    #[test]
+    fn malformed_headers() {
+        assert!(matches!(parse("2"), Err(ProtocolError::HeaderTooShort)));
+        assert!(matches!(
+            parse("20text/gemini"),
+            Err(ProtocolError::HeaderMalformed)
+        ));
+        assert!(matches!(
+            parse("XX text/gemini"),
+            Err(ProtocolError::HeaderMalformed)
+        ));
+        assert!(matches!(
+            parse("30 http://[::1"),
+            Err(ProtocolError::InvalidRedirect(_))
+        ));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::request::UrlError;
+use percent_encoding::percent_decode_str;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use url::Url;
+use webpki::{DNSNameRef, InvalidDNSNameError};
+
+/// Host a connection is made to, and which the server certificate is verified for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Host {
+    /// DNS name in ASCII form. Internationalized names are converted to punycode.
+    Domain(String),
+    /// IP address literal.
+    Ip(IpAddr),
+}
+
+/// Name used for sessions with IP addresses. It is neither sent to servers nor verified.
+const IP_SESSION_NAME: &str = "ip-address.invalid";
+
+impl Host {
+    /// Extracts host of the URL.
+    pub fn from_url(url: &Url) -> Result<Self, UrlError> {
+        match url.host() {
+            None => Err(UrlError::MissingHost),
+            Some(url::Host::Ipv4(ip)) => Ok(Host::Ip(ip.into())),
+            Some(url::Host::Ipv6(ip)) => Ok(Host::Ip(ip.into())),
+            Some(url::Host::Domain(domain)) => Self::from_domain(domain),
+        }
+    }
+
+    // URLs with "gemini" scheme are not special for the url crate. It keeps their hosts
+    // percent-encoded and does not recognize IPv4 addresses in them.
+    fn from_domain(domain: &str) -> Result<Self, UrlError> {
+        let invalid = || UrlError::InvalidHost(domain.to_owned());
+        if domain.is_empty() {
+            return Err(UrlError::MissingHost);
+        }
+        let domain = percent_decode_str(domain)
+            .decode_utf8()
+            .map_err(|_| invalid())?;
+        if let Ok(ip) = domain.parse::<Ipv4Addr>() {
+            return Ok(Host::Ip(ip.into()));
+        }
+        let ascii = idna::domain_to_ascii(&domain).map_err(|_| invalid())?;
+        DNSNameRef::try_from_ascii_str(&ascii).map_err(|_| invalid())?;
+        Ok(Host::Domain(ascii))
+    }
+
+    /// Name for rustls session. Sessions with IP addresses must have SNI disabled.
+    pub(crate) fn session_name(&self) -> Result<DNSNameRef<'_>, InvalidDNSNameError> {
+        match self {
+            Host::Domain(domain) => DNSNameRef::try_from_ascii_str(domain),
+            Host::Ip(_) => DNSNameRef::try_from_ascii_str(IP_SESSION_NAME),
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Domain(domain) => write!(f, "{}", domain),
+            Host::Ip(IpAddr::V4(ip)) => write!(f, "{}", ip),
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{}]", ip),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(url: &str) -> Result<Host, UrlError> {
+        Host::from_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn ip_literals() {
+        assert_eq!(
+            host("gemini://127.0.0.1/"),
+            Ok(Host::Ip("127.0.0.1".parse().unwrap()))
+        );
+        assert_eq!(
+            host("gemini://[::1]:1966/"),
+            Ok(Host::Ip("::1".parse().unwrap()))
+        );
+        assert_eq!(host("gemini://[::1]/").unwrap().to_string(), "[::1]");
+    }
+
+    #[test]
+    fn internationalized_domains() {
+        assert_eq!(
+            host("gemini://пример.рф/"),
+            Ok(Host::Domain("xn--e1afmkfd.xn--p1ai".to_owned()))
+        );
+        assert_eq!(
+            host("gemini://Example.COM/"),
+            Ok(Host::Domain("example.com".to_owned()))
+        );
+    }
+
+    #[test]
+    fn invalid_hosts() {
+        assert!(matches!(
+            host("gemini://%FF/"),
+            Err(UrlError::InvalidHost(_))
+        ));
+        assert!(matches!(
+            host("gemini://exa%20mple.com/"),
+            Err(UrlError::InvalidHost(_))
+        ));
+        assert_eq!(host("gemini:///path"), Err(UrlError::MissingHost));
+    }
+}
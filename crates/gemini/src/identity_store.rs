@@ -0,0 +1,722 @@
+use crate::fingerprints::Fingerprint;
+use crate::host::Host;
+use crate::identity::{self, Identity, IdentityResolver};
+use crate::request::{normalize_url, DEFAULT_GEMINI_PORT};
+use crate::storage;
+use crate::x509;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use rustls::{Certificate, PrivateKey};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use url::Url;
+use zeroize::Zeroizing;
+
+/// Validity of generated certificates, unless specified otherwise.
+pub const DEFAULT_VALIDITY: Duration = Duration::from_secs(5 * 365 * 24 * 60 * 60);
+
+const INDEX_FILE: &str = "index";
+const INDEX_HEADER: &str = "# Client identities: file name, identity name, scopes";
+
+// Names may contain anything, but spaces separate fields of the index.
+const NAME_ENCODE_SET: &AsciiSet = &CONTROLS.add(b' ').add(b'%');
+
+/// Persistent collection of client identities, bound to URL prefixes.
+///
+/// Each identity is stored as a pair of DER files, certificate chain and private key.
+/// Their names and scopes are kept in a line-based index file.
+/// All changes are written to disk before they take effect.
+pub struct IdentityStore {
+    directory: PathBuf,
+    identities: Mutex<BTreeMap<String, StoredIdentity>>,
+}
+
+#[derive(Clone)]
+struct StoredIdentity {
+    file_name: String,
+    identity: Arc<Identity>,
+    common_name: String,
+    not_after: SystemTime,
+    scopes: Vec<Scope>,
+}
+
+/// Stored identity, as listed by `IdentityStore::list`.
+#[derive(Clone, Debug)]
+pub struct IdentityInfo {
+    pub name: String,
+    pub common_name: String,
+    pub not_after: SystemTime,
+    pub scopes: Vec<Scope>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    EcdsaP256,
+    EcdsaP384,
+    Ed25519,
+}
+
+impl KeyType {
+    fn algorithm(self) -> &'static rcgen::SignatureAlgorithm {
+        match self {
+            KeyType::EcdsaP256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            KeyType::EcdsaP384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+            KeyType::Ed25519 => &rcgen::PKCS_ED25519,
+        }
+    }
+}
+
+/// Parameters of generated self-signed certificates.
+#[derive(Clone, Debug)]
+pub struct IdentityParams {
+    pub common_name: String,
+    pub not_after: SystemTime,
+    pub key_type: KeyType,
+}
+
+impl IdentityParams {
+    /// Certificate for `common_name` with ECDSA P-256 key, valid for `DEFAULT_VALIDITY`.
+    pub fn new(common_name: &str) -> Self {
+        Self {
+            common_name: common_name.to_owned(),
+            not_after: SystemTime::now() + DEFAULT_VALIDITY,
+            key_type: KeyType::EcdsaP256,
+        }
+    }
+}
+
+/// URL prefix which an identity is presented for: host, port, and path.
+///
+/// Prefixes match whole path segments: "/app" matches "/app" and "/app/page", but not "/apple".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Scope {
+    host: Host,
+    port: u16,
+    path: String,
+}
+
+impl Scope {
+    /// Parses "gemini://host/path" URL, or just "host/path".
+    ///
+    /// Query and fragment, if present, are ignored.
+    pub fn parse(scope: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidScope(scope.to_owned());
+        let url = if scope.contains("://") {
+            Url::parse(scope)
+        } else {
+            Url::parse(&format!("gemini://{}", scope))
+        };
+        let url = normalize_url(url.map_err(|_| invalid())?).map_err(|_| invalid())?;
+        if url.scheme() != "gemini" {
+            return Err(invalid());
+        }
+        Ok(Self {
+            host: Host::from_url(&url).map_err(|_| invalid())?,
+            port: url.port().unwrap_or(DEFAULT_GEMINI_PORT),
+            path: non_empty_path(&url).to_owned(),
+        })
+    }
+
+    pub fn matches(&self, url: &Url) -> bool {
+        if url.scheme() != "gemini" || url.port().unwrap_or(DEFAULT_GEMINI_PORT) != self.port {
+            return false;
+        }
+        if Host::from_url(url).as_ref() != Ok(&self.host) {
+            return false;
+        }
+        let path = non_empty_path(url);
+        match path.strip_prefix(self.path.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || self.path.ends_with('/'),
+            None => false,
+        }
+    }
+
+    /// Longer prefixes are more specific.
+    fn specificity(&self) -> usize {
+        self.path.len()
+    }
+}
+
+// Gemini URLs may have empty paths, which are equivalent to "/".
+fn non_empty_path(url: &Url) -> &str {
+    match url.path() {
+        "" => "/",
+        path => path,
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "gemini://{}", self.host)?;
+        if self.port != DEFAULT_GEMINI_PORT {
+            write!(f, ":{}", self.port)?;
+        }
+        write!(f, "{}", self.path)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error("failed to generate certificate: {0}")]
+    Generation(#[from] rcgen::RcgenError),
+    #[error("identity {0:?} does not exist")]
+    NotFound(String),
+    #[error("identity {0:?} already exists")]
+    AlreadyExists(String),
+    #[error("failed to import or export identity: {0}")]
+    Import(#[from] identity::Error),
+    #[error("identity name must not be empty")]
+    InvalidName,
+    #[error("invalid scope: {0}")]
+    InvalidScope(String),
+    #[error("corrupted identity store: {0}")]
+    Corrupted(String),
+}
+
+impl IdentityStore {
+    /// Opens the store in `directory`, creating it if necessary.
+    pub fn open(directory: impl AsRef<Path>) -> Result<Self, Error> {
+        let directory = directory.as_ref().to_owned();
+        fs::create_dir_all(&directory)?;
+        let identities = load_index(&directory)?;
+        Ok(Self {
+            directory,
+            identities: Mutex::new(identities),
+        })
+    }
+
+    /// Generates new self-signed certificate and stores it as identity `name`.
+    pub fn generate(&self, name: &str, params: &IdentityParams) -> Result<Arc<Identity>, Error> {
+        self.insert(name, || generate_certificate(params))
+    }
+
+    /// Imports PEM-encoded certificate chain and private key as identity `name`.
+    pub fn import_pem(
+        &self,
+        name: &str,
+        certificates: &[u8],
+        key: &[u8],
+    ) -> Result<Arc<Identity>, Error> {
+        self.insert(name, || Ok(Identity::from_pem(certificates, key)?))
+    }
+
+    /// Imports PKCS#12 bundle, protected by `passphrase` if any, as identity `name`.
+    pub fn import_pkcs12(
+        &self,
+        name: &str,
+        der: &[u8],
+        passphrase: Option<&str>,
+    ) -> Result<Arc<Identity>, Error> {
+        self.insert(name, || Ok(Identity::from_pkcs12(der, passphrase)?))
+    }
+
+    /// Exports identity `name` as PEM-encoded certificate chain and private key.
+    pub fn export_pem(&self, name: &str) -> Result<(String, Zeroizing<String>), Error> {
+        let identity = self
+            .get(name)
+            .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+        Ok(identity.to_pem())
+    }
+
+    /// Exports identity `name` as PKCS#12 bundle, protected by `passphrase` if any.
+    pub fn export_pkcs12(
+        &self,
+        name: &str,
+        passphrase: Option<&str>,
+    ) -> Result<Zeroizing<Vec<u8>>, Error> {
+        let identity = self
+            .get(name)
+            .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+        Ok(identity.to_pkcs12(passphrase, name)?)
+    }
+
+    /// Stores identity produced by `create` under a new `name`.
+    fn insert(
+        &self,
+        name: &str,
+        create: impl FnOnce() -> Result<Identity, Error>,
+    ) -> Result<Arc<Identity>, Error> {
+        if name.trim().is_empty() {
+            return Err(Error::InvalidName);
+        }
+        let mut written = None;
+        let result = self.update(|identities| {
+            if identities.contains_key(name) {
+                return Err(Error::AlreadyExists(name.to_owned()));
+            }
+            let identity = create()?;
+            let certificates = identity.certificates();
+            let file_name = hex(Fingerprint::new(&certificates[0].0).bytes());
+            // Files are named after the certificate, the same one cannot be stored twice.
+            if let Some((other, _)) = identities
+                .iter()
+                .find(|(_, stored)| stored.file_name == file_name)
+            {
+                return Err(Error::AlreadyExists(other.clone()));
+            }
+            let chain = certificates
+                .iter()
+                .flat_map(|certificate| certificate.0.iter().copied())
+                .collect::<Vec<_>>();
+            written = Some(file_name.clone());
+            storage::write_private(&key_path(&self.directory, &file_name), &identity.key().0)?;
+            storage::write_atomically(&certificate_path(&self.directory, &file_name), &chain)?;
+            let stored = load_identity(&self.directory, &file_name)?;
+            let identity = stored.identity.clone();
+            identities.insert(name.to_owned(), stored);
+            Ok(identity)
+        });
+        // Files not referenced by the index would never be removed, key included.
+        if let (Err(_), Some(file_name)) = (&result, written) {
+            let _ = storage::remove_file(&key_path(&self.directory, &file_name));
+            let _ = storage::remove_file(&certificate_path(&self.directory, &file_name));
+        }
+        result
+    }
+
+    pub fn list(&self) -> Vec<IdentityInfo> {
+        let identities = self.identities.lock().unwrap();
+        identities
+            .iter()
+            .map(|(name, stored)| IdentityInfo {
+                name: name.clone(),
+                common_name: stored.common_name.clone(),
+                not_after: stored.not_after,
+                scopes: stored.scopes.clone(),
+            })
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Identity>> {
+        let identities = self.identities.lock().unwrap();
+        identities.get(name).map(|stored| stored.identity.clone())
+    }
+
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        if new_name.trim().is_empty() {
+            return Err(Error::InvalidName);
+        }
+        self.update(|identities| {
+            if identities.contains_key(new_name) {
+                return Err(Error::AlreadyExists(new_name.to_owned()));
+            }
+            let stored = identities
+                .remove(name)
+                .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+            identities.insert(new_name.to_owned(), stored);
+            Ok(())
+        })
+    }
+
+    /// Presents identity `name` for requests within `scope`.
+    ///
+    /// If the scope has been bound to another identity, it is unbound from it.
+    pub fn bind(&self, name: &str, scope: Scope) -> Result<(), Error> {
+        self.update(|identities| {
+            if !identities.contains_key(name) {
+                return Err(Error::NotFound(name.to_owned()));
+            }
+            for stored in identities.values_mut() {
+                stored.scopes.retain(|other| *other != scope);
+            }
+            let stored = identities.get_mut(name).expect("checked above");
+            stored.scopes.push(scope);
+            Ok(())
+        })
+    }
+
+    /// Stops presenting identity `name` for requests within `scope`.
+    pub fn unbind(&self, name: &str, scope: &Scope) -> Result<(), Error> {
+        self.update(|identities| {
+            let stored = identities
+                .get_mut(name)
+                .ok_or_else(|| Error::NotFound(name.to_owned()))?;
+            stored.scopes.retain(|other| other != scope);
+            Ok(())
+        })
+    }
+
+    /// Removes identity `name` along with its certificate and private key.
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        let removed = self.update(|identities| {
+            identities
+                .remove(name)
+                .ok_or_else(|| Error::NotFound(name.to_owned()))
+        })?;
+        storage::remove_file(&certificate_path(&self.directory, &removed.file_name))?;
+        storage::remove_file(&key_path(&self.directory, &removed.file_name))?;
+        Ok(())
+    }
+
+    /// Applies changes to a copy of identities, which replaces them once written to disk.
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, StoredIdentity>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut identities = self.identities.lock().unwrap();
+        let mut updated = identities.clone();
+        let result = change(&mut updated)?;
+        save_index(&self.directory, &updated)?;
+        *identities = updated;
+        Ok(result)
+    }
+}
+
+impl IdentityResolver for IdentityStore {
+    /// Identity bound to the most specific scope of the URL.
+    fn resolve_identity(&self, url: &Url) -> Option<Arc<Identity>> {
+        let identities = self.identities.lock().unwrap();
+        identities
+            .values()
+            .flat_map(|stored| {
+                stored
+                    .scopes
+                    .iter()
+                    .filter(|scope| scope.matches(url))
+                    .map(move |scope| (scope.specificity(), stored))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, stored)| stored.identity.clone())
+    }
+}
+
+fn generate_certificate(params: &IdentityParams) -> Result<Identity, Error> {
+    let mut certificate_params = rcgen::CertificateParams::default();
+    certificate_params.alg = params.key_type.algorithm();
+    certificate_params.distinguished_name = rcgen::DistinguishedName::new();
+    certificate_params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, params.common_name.as_str());
+    certificate_params.not_before = chrono::Utc::now();
+    certificate_params.not_after = params.not_after.into();
+    let certificate = rcgen::Certificate::from_params(certificate_params)?;
+    Ok(Identity::new(
+        vec![Certificate(certificate.serialize_der()?)],
+        PrivateKey(certificate.serialize_private_key_der()),
+    ))
+}
+
+fn certificate_path(directory: &Path, file_name: &str) -> PathBuf {
+    directory.join(format!("{}.crt", file_name))
+}
+
+fn key_path(directory: &Path, file_name: &str) -> PathBuf {
+    directory.join(format!("{}.key", file_name))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn load_index(directory: &Path) -> Result<BTreeMap<String, StoredIdentity>, Error> {
+    let contents = match fs::read_to_string(directory.join(INDEX_FILE)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut identities = BTreeMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let corrupted = || Error::Corrupted(format!("malformed line {} of the index", number + 1));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(' ');
+        let file_name = fields.next().ok_or_else(corrupted)?;
+        // File names come from the index, make sure they stay in the directory.
+        if file_name.is_empty() || !file_name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(corrupted());
+        }
+        let name = fields.next().ok_or_else(corrupted)?;
+        let name = percent_decode_str(name)
+            .decode_utf8()
+            .map_err(|_| corrupted())?
+            .into_owned();
+        let mut stored = load_identity(directory, file_name)?;
+        for scope in fields {
+            stored
+                .scopes
+                .push(Scope::parse(scope).map_err(|_| corrupted())?);
+        }
+        identities.insert(name, stored);
+    }
+    Ok(identities)
+}
+
+fn save_index(
+    directory: &Path,
+    identities: &BTreeMap<String, StoredIdentity>,
+) -> Result<(), Error> {
+    let mut contents = String::new();
+    contents.push_str(INDEX_HEADER);
+    contents.push('\n');
+    for (name, stored) in identities {
+        contents.push_str(&stored.file_name);
+        contents.push(' ');
+        contents.extend(utf8_percent_encode(name, NAME_ENCODE_SET));
+        for scope in &stored.scopes {
+            contents.push(' ');
+            contents.push_str(&scope.to_string());
+        }
+        contents.push('\n');
+    }
+    storage::write_atomically(&directory.join(INDEX_FILE), contents.as_bytes())?;
+    Ok(())
+}
+
+fn load_identity(directory: &Path, file_name: &str) -> Result<StoredIdentity, Error> {
+    let chain = fs::read(certificate_path(directory, file_name))?;
+    let mut key = Zeroizing::new(fs::read(key_path(directory, file_name))?);
+    let corrupted = || Error::Corrupted(format!("malformed certificate {}", file_name));
+    // Certificate chain is stored as concatenated DER, starting with the client certificate.
+    let mut certificates = Vec::new();
+    let mut remaining = &chain[..];
+    while !remaining.is_empty() {
+        let (rest, _) = x509_parser::parse_x509_certificate(remaining).map_err(|_| corrupted())?;
+        let length = remaining.len() - rest.len();
+        certificates.push(Certificate(remaining[..length].to_vec()));
+        remaining = rest;
+    }
+    let (common_name, not_after) = {
+        let first = certificates.first().ok_or_else(corrupted)?;
+        let (_, parsed) = x509_parser::parse_x509_certificate(&first.0).map_err(|_| corrupted())?;
+        let common_name = parsed
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        (common_name, x509::system_time(parsed.validity().not_after))
+    };
+    Ok(StoredIdentity {
+        file_name: file_name.to_owned(),
+        identity: Arc::new(Identity::new(
+            certificates,
+            PrivateKey(mem::take(&mut *key)),
+        )),
+        common_name,
+        not_after,
+        scopes: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TemporaryDirectory;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn scopes() {
+        let scope = Scope::parse("example.com/app").unwrap();
+        assert_eq!(scope.to_string(), "gemini://example.com/app");
+        assert!(scope.matches(&url("gemini://example.com/app")));
+        assert!(scope.matches(&url("gemini://EXAMPLE.com:1965/app/page?query")));
+        assert!(!scope.matches(&url("gemini://example.com/apple")));
+        assert!(!scope.matches(&url("gemini://example.com:1966/app")));
+        assert!(!scope.matches(&url("gemini://sub.example.com/app")));
+
+        let scope = Scope::parse("gemini://example.com:1966").unwrap();
+        assert_eq!(scope.to_string(), "gemini://example.com:1966/");
+        assert!(scope.matches(&url("gemini://example.com:1966")));
+        assert!(scope.matches(&url("gemini://example.com:1966/page")));
+
+        assert!(Scope::parse("https://example.com/").is_err());
+        assert!(Scope::parse("user@example.com/").is_err());
+    }
+
+    #[test]
+    fn generated_identities_are_usable() {
+        let directory = TemporaryDirectory::new();
+        let store = IdentityStore::open(&directory.0).unwrap();
+        for (name, key_type) in &[
+            ("p256", KeyType::EcdsaP256),
+            ("p384", KeyType::EcdsaP384),
+            ("ed25519", KeyType::Ed25519),
+        ] {
+            let params = IdentityParams {
+                key_type: *key_type,
+                ..IdentityParams::new("Alice")
+            };
+            let identity = store.generate(name, &params).unwrap();
+            assert!(identity.certified_key().is_ok());
+        }
+        let list = store.list();
+        assert_eq!(list.len(), 3);
+        assert!(list.iter().all(|info| info.common_name == "Alice"));
+        assert!(matches!(
+            store.generate("p256", &IdentityParams::new("Bob")),
+            Err(Error::AlreadyExists(_))
+        ));
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let directory = TemporaryDirectory::new();
+        let store = IdentityStore::open(&directory.0).unwrap();
+        let site = store
+            .generate("site", &IdentityParams::new("site"))
+            .unwrap();
+        let app = store.generate("app", &IdentityParams::new("app")).unwrap();
+        store
+            .bind("site", Scope::parse("example.com/").unwrap())
+            .unwrap();
+        store
+            .bind("app", Scope::parse("example.com/app/").unwrap())
+            .unwrap();
+
+        let resolve = |s| {
+            store
+                .resolve_identity(&url(s))
+                .map(|identity| identity.certificates()[0].clone())
+        };
+        assert_eq!(
+            resolve("gemini://example.com/app/page"),
+            Some(app.certificates()[0].clone())
+        );
+        assert_eq!(
+            resolve("gemini://example.com/other"),
+            Some(site.certificates()[0].clone())
+        );
+        assert_eq!(resolve("gemini://example.org/"), None);
+
+        // Rebinding moves the scope over.
+        store
+            .bind("site", Scope::parse("example.com/app/").unwrap())
+            .unwrap();
+        assert_eq!(
+            resolve("gemini://example.com/app/page"),
+            Some(site.certificates()[0].clone())
+        );
+        assert!(store
+            .list()
+            .iter()
+            .find(|info| info.name == "app")
+            .unwrap()
+            .scopes
+            .is_empty());
+    }
+
+    #[test]
+    fn changes_persist() {
+        let directory = TemporaryDirectory::new();
+        {
+            let store = IdentityStore::open(&directory.0).unwrap();
+            store
+                .generate("first one", &IdentityParams::new("A"))
+                .unwrap();
+            store.generate("second", &IdentityParams::new("B")).unwrap();
+            store.generate("third", &IdentityParams::new("C")).unwrap();
+            let scope = Scope::parse("example.com/").unwrap();
+            store.bind("first one", scope.clone()).unwrap();
+            store
+                .bind("second", Scope::parse("example.org/").unwrap())
+                .unwrap();
+            store
+                .unbind("second", &Scope::parse("example.org/").unwrap())
+                .unwrap();
+            store.rename("first one", "renamed 100%").unwrap();
+            store.delete("third").unwrap();
+            assert!(matches!(store.delete("third"), Err(Error::NotFound(_))));
+        }
+        let store = IdentityStore::open(&directory.0).unwrap();
+        let list = store.list();
+        let names = list
+            .iter()
+            .map(|info| info.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["renamed 100%", "second"]);
+        assert_eq!(list[0].scopes, [Scope::parse("example.com/").unwrap()]);
+        assert!(list[1].scopes.is_empty());
+        assert!(store
+            .resolve_identity(&url("gemini://example.com/"))
+            .is_some());
+        // Certificate, key, and index files.
+        assert_eq!(fs::read_dir(&directory.0).unwrap().count(), 5);
+    }
+
+    #[test]
+    fn failed_insert_leaves_no_files() {
+        let directory = TemporaryDirectory::new();
+        let store = IdentityStore::open(&directory.0).unwrap();
+        // Index cannot be replaced by a file.
+        fs::create_dir(directory.0.join(INDEX_FILE)).unwrap();
+        assert!(store.generate("name", &IdentityParams::new("A")).is_err());
+        assert!(store.list().is_empty());
+        let files = fs::read_dir(&directory.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(files, [INDEX_FILE]);
+    }
+
+    #[test]
+    fn import_and_export() {
+        let issuer = {
+            let mut params = rcgen::CertificateParams::new(vec![]);
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            rcgen::Certificate::from_params(params).unwrap()
+        };
+        let client =
+            rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec![])).unwrap();
+        let chain = Identity::new(
+            vec![
+                Certificate(client.serialize_der_with_signer(&issuer).unwrap()),
+                Certificate(issuer.serialize_der().unwrap()),
+            ],
+            PrivateKey(client.serialize_private_key_der()),
+        );
+        let (certificates, key) = chain.to_pem();
+        let fingerprints = |identity: &Identity| {
+            identity
+                .certificates()
+                .iter()
+                .map(|certificate| Fingerprint::new(&certificate.0))
+                .collect::<Vec<_>>()
+        };
+
+        let directory = TemporaryDirectory::new();
+        let exported = {
+            let store = IdentityStore::open(&directory.0).unwrap();
+            store
+                .import_pem("chain", certificates.as_bytes(), key.as_bytes())
+                .unwrap();
+            assert!(matches!(
+                store.import_pem("again", certificates.as_bytes(), key.as_bytes()),
+                Err(Error::AlreadyExists(name)) if name == "chain"
+            ));
+            store.export_pkcs12("chain", Some("passphrase")).unwrap()
+        };
+
+        // Chain is stored along with the client certificate.
+        let store = IdentityStore::open(&directory.0).unwrap();
+        let (reexported, _) = store.export_pem("chain").unwrap();
+        assert_eq!(reexported, certificates);
+
+        let directory = TemporaryDirectory::new();
+        let store = IdentityStore::open(&directory.0).unwrap();
+        assert!(matches!(
+            store.import_pkcs12("chain", &exported, None),
+            Err(Error::Import(identity::Error::Decryption))
+        ));
+        let imported = store
+            .import_pkcs12("chain", &exported, Some("passphrase"))
+            .unwrap();
+        assert_eq!(fingerprints(&imported), fingerprints(&chain));
+        assert!(matches!(
+            store.export_pem("missing"),
+            Err(Error::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,524 @@
+use crate::fingerprints::Fingerprint;
+use crate::host::Host;
+use crate::request::DEFAULT_GEMINI_PORT;
+use crate::storage;
+use crate::verify::{CertificateTrustCache, Endpoint, PinnedCertificate, Response};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use x509_parser::certificate::X509Certificate;
+
+const HEADER: &str = "# Known hosts: host[:port], fingerprint, first seen, last seen, \
+                      not after, public key, not before, names";
+
+// Spaces separate fields, commas separate names.
+const NAME_ENCODE_SET: &AsciiSet = &CONTROLS.add(b' ').add(b',').add(b'%');
+
+/// Placeholder for unknown values.
+const UNKNOWN: &str = "-";
+
+/// Last seen time is written to disk only once it moves this far,
+/// so that connections to known hosts do not rewrite the file each time.
+const LAST_SEEN_PRECISION: Duration = Duration::from_secs(60 * 60);
+
+/// Persistent `CertificateTrustCache`, in the spirit of SSH known_hosts file.
+///
+/// Each line of the file holds a host, fingerprint of the certificate trusted for it,
+/// and timestamps. Certificates trusted always are written to disk before they take effect.
+/// Certificates trusted once are kept in memory, for as long as the store lives.
+pub struct KnownHosts {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+struct State {
+    hosts: HashMap<(Host, u16), KnownHost>,
+    session: HashMap<(Host, u16), PinnedCertificate>,
+}
+
+/// Certificate trusted for a host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnownHost {
+    pub host: Host,
+    pub port: u16,
+    pub certificate: PinnedCertificate,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error("host {0} is not known")]
+    NotFound(String),
+    #[error("corrupted known hosts file: {0}")]
+    Corrupted(String),
+}
+
+impl KnownHosts {
+    /// Opens known hosts file at `path`, which is created once something is trusted.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        let hosts = load(&path)?;
+        Ok(Self {
+            path,
+            state: Mutex::new(State {
+                hosts,
+                session: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Certificate trusted always for the host, if any.
+    pub fn get(&self, host: &Host, port: u16) -> Option<KnownHost> {
+        let state = self.state.lock().unwrap();
+        state.hosts.get(&(host.clone(), port)).cloned()
+    }
+
+    pub fn list(&self) -> Vec<KnownHost> {
+        let state = self.state.lock().unwrap();
+        let mut hosts = state.hosts.values().cloned().collect::<Vec<_>>();
+        hosts.sort_by_key(|known| key(&known.host, known.port));
+        hosts
+    }
+
+    /// Trusts the certificate for the host until the store is dropped.
+    pub fn trust_once(&self, host: &Host, port: u16, certificate: &PinnedCertificate) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .session
+            .insert((host.clone(), port), certificate.clone());
+    }
+
+    /// Trusts the certificate for the host from now on, replacing the previous one.
+    pub fn trust_always(
+        &self,
+        host: &Host,
+        port: u16,
+        certificate: &PinnedCertificate,
+    ) -> Result<(), Error> {
+        let now = SystemTime::now();
+        self.update(|state| {
+            let entry = (host.clone(), port);
+            state.session.remove(&entry);
+            let first_seen = match state.hosts.get(&entry) {
+                Some(known) if known.certificate.fingerprint == certificate.fingerprint => {
+                    known.first_seen
+                }
+                _ => now,
+            };
+            state.hosts.insert(
+                entry,
+                KnownHost {
+                    host: host.clone(),
+                    port,
+                    certificate: certificate.clone(),
+                    first_seen,
+                    last_seen: now,
+                },
+            );
+            Ok(())
+        })
+    }
+
+    /// Forgets certificates trusted for the host, both once and always.
+    pub fn forget(&self, host: &Host, port: u16) -> Result<(), Error> {
+        self.update(|state| {
+            let entry = (host.clone(), port);
+            let session = state.session.remove(&entry);
+            match (state.hosts.remove(&entry), session) {
+                (None, None) => Err(Error::NotFound(key(host, port))),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// Trusted certificate with the fingerprint, or why it's not trusted.
+    fn lookup(
+        &self,
+        host: &Host,
+        port: u16,
+        fingerprint: &Fingerprint,
+    ) -> Result<PinnedCertificate, Response> {
+        let mut state = self.state.lock().unwrap();
+        let entry = (host.clone(), port);
+        let session = state.session.get(&entry).cloned();
+        let known = match (state.hosts.get_mut(&entry), session) {
+            (_, Some(session)) if session.fingerprint == *fingerprint => return Ok(session),
+            (Some(known), _) if known.certificate.fingerprint == *fingerprint => known,
+            // Certificate trusted once is a more recent decision.
+            (_, Some(session)) => return Err(Response::FingerprintMismatch(session)),
+            (Some(known), None) => {
+                return Err(Response::FingerprintMismatch(known.certificate.clone()))
+            }
+            (None, None) => return Err(Response::UnknownCertificate),
+        };
+        let certificate = known.certificate.clone();
+        let now = SystemTime::now();
+        let elapsed = now.duration_since(known.last_seen).unwrap_or_default();
+        if elapsed >= LAST_SEEN_PRECISION {
+            known.last_seen = now;
+            // Not being able to record the time does not make the certificate less trusted.
+            let _ = save(&self.path, &state.hosts);
+        }
+        Ok(certificate)
+    }
+
+    /// Applies changes to a copy of known hosts, which replaces them once written to disk.
+    fn update<T>(&self, change: impl FnOnce(&mut State) -> Result<T, Error>) -> Result<T, Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut updated = State {
+            hosts: state.hosts.clone(),
+            session: state.session.clone(),
+        };
+        let result = change(&mut updated)?;
+        save(&self.path, &updated.hosts)?;
+        *state = updated;
+        Ok(result)
+    }
+}
+
+impl CertificateTrustCache for KnownHosts {
+    fn get_trusted_certificate(
+        &self,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    ) -> Option<PinnedCertificate> {
+        self.lookup(&endpoint.host, endpoint.port, fingerprint).ok()
+    }
+
+    fn get_certificate_trust(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    ) -> Response {
+        match self.lookup(&endpoint.host, endpoint.port, fingerprint) {
+            Ok(_) => Response::TrustedCertificate,
+            Err(response) => response,
+        }
+    }
+
+    fn trust_certificate_once(
+        &self,
+        certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    ) {
+        let certificate = PinnedCertificate::new(certificate, fingerprint);
+        self.trust_once(&endpoint.host, endpoint.port, &certificate);
+    }
+
+    fn trust_certificate_always(
+        &self,
+        certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    ) {
+        let certificate = PinnedCertificate::new(certificate, fingerprint);
+        let result = self.trust_always(&endpoint.host, endpoint.port, &certificate);
+        // If the decision cannot be persisted, honor it at least for this session.
+        if result.is_err() {
+            self.trust_once(&endpoint.host, endpoint.port, &certificate);
+        }
+    }
+}
+
+/// Host field of the file. Port is omitted when it's the default one.
+fn key(host: &Host, port: u16) -> String {
+    if port == DEFAULT_GEMINI_PORT {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn parse_key(key: &str) -> Option<(Host, u16)> {
+    let url = Url::parse(&format!("gemini://{}/", key)).ok()?;
+    let host = Host::from_url(&url).ok()?;
+    let port = url.port().unwrap_or(DEFAULT_GEMINI_PORT);
+    // Anything but the canonical form suggests the field is not just a host.
+    if self::key(&host, port) != key {
+        return None;
+    }
+    Some((host, port))
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn time(seconds: &str) -> Option<SystemTime> {
+    let seconds = seconds.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+fn optional(value: Option<String>) -> String {
+    value.unwrap_or_else(|| UNKNOWN.to_owned())
+}
+
+fn format_names(names: &[String]) -> String {
+    let names = names
+        .iter()
+        .map(|name| utf8_percent_encode(name, NAME_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    match names.as_str() {
+        "" => UNKNOWN.to_owned(),
+        // Not to be confused with the placeholder.
+        "-" => "%2D".to_owned(),
+        _ => names,
+    }
+}
+
+fn parse_names(names: &str) -> Option<Vec<String>> {
+    names
+        .split(',')
+        .map(|name| {
+            let name = percent_decode_str(name).decode_utf8().ok()?;
+            Some(name.into_owned())
+        })
+        .collect()
+}
+
+fn load(path: &Path) -> Result<HashMap<(Host, u16), KnownHost>, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut hosts = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let corrupted = || Error::Corrupted(format!("malformed line {}", number + 1));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(' ');
+        let mut required = || fields.next().ok_or_else(corrupted);
+        let (host, port) = parse_key(required()?).ok_or_else(corrupted)?;
+        let fingerprint = required()?.parse().map_err(|_| corrupted())?;
+        let first_seen = time(required()?).ok_or_else(corrupted)?;
+        let last_seen = time(required()?).ok_or_else(corrupted)?;
+        let not_after = time(required()?).ok_or_else(corrupted)?;
+        // Fields below have been added later, older lines may lack them.
+        let mut optional = || fields.next().filter(|field| *field != UNKNOWN);
+        let public_key = match optional() {
+            Some(field) => Some(field.parse().map_err(|_| corrupted())?),
+            None => None,
+        };
+        let not_before = match optional() {
+            Some(field) => Some(time(field).ok_or_else(corrupted)?),
+            None => None,
+        };
+        let names = match optional() {
+            Some(field) => parse_names(field).ok_or_else(corrupted)?,
+            None => Vec::new(),
+        };
+        if fields.next().is_some() {
+            return Err(corrupted());
+        }
+        let known = KnownHost {
+            host: host.clone(),
+            port,
+            certificate: PinnedCertificate {
+                fingerprint,
+                public_key,
+                names,
+                not_before,
+                not_after,
+            },
+            first_seen,
+            last_seen,
+        };
+        hosts.insert((host, port), known);
+    }
+    Ok(hosts)
+}
+
+fn save(path: &Path, hosts: &HashMap<(Host, u16), KnownHost>) -> Result<(), Error> {
+    let mut lines = hosts
+        .values()
+        .map(|known| {
+            let certificate = &known.certificate;
+            format!(
+                "{} {} {} {} {} {} {} {}\n",
+                key(&known.host, known.port),
+                certificate.fingerprint,
+                seconds(known.first_seen),
+                seconds(known.last_seen),
+                seconds(certificate.not_after),
+                optional(certificate.public_key.as_ref().map(ToString::to_string)),
+                optional(certificate.not_before.map(|time| seconds(time).to_string())),
+                format_names(&certificate.names),
+            )
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    let mut contents = String::new();
+    contents.push_str(HEADER);
+    contents.push('\n');
+    contents.extend(lines);
+    storage::write_atomically(path, contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TemporaryDirectory;
+    use crate::test_support::{self, endpoint};
+
+    fn host(name: &str) -> Host {
+        Host::from_url(&Url::parse(&format!("gemini://{}/", name)).unwrap()).unwrap()
+    }
+
+    fn certificate() -> Vec<u8> {
+        test_support::certificate(None).0 .0
+    }
+
+    #[test]
+    fn trust_once_and_always() {
+        let directory = TemporaryDirectory::new();
+        let path = directory.0.join("known_hosts");
+        let (first, second) = (certificate(), certificate());
+        let (_, parsed) = x509_parser::parse_x509_certificate(&first).unwrap();
+        let fingerprint = Fingerprint::new(&first);
+        let other = Fingerprint::new(&second);
+        let example = endpoint("gemini://example.com/");
+        let other_port = endpoint("gemini://example.com:1966/");
+        {
+            let known_hosts = KnownHosts::open(&path).unwrap();
+            let trust =
+                |fingerprint| known_hosts.get_certificate_trust(&parsed, fingerprint, &example);
+            assert!(matches!(trust(&fingerprint), Response::UnknownCertificate));
+
+            let mismatch = |response| matches!(response, Response::FingerprintMismatch(pinned) if pinned.fingerprint == fingerprint);
+
+            known_hosts.trust_certificate_once(&parsed, &fingerprint, &example);
+            assert!(matches!(trust(&fingerprint), Response::TrustedCertificate));
+            assert!(mismatch(trust(&other)));
+            assert!(!path.exists());
+
+            known_hosts.trust_certificate_always(&parsed, &fingerprint, &example);
+            assert!(matches!(trust(&fingerprint), Response::TrustedCertificate));
+            assert!(mismatch(trust(&other)));
+            // Other ports are different capsules.
+            assert!(matches!(
+                known_hosts.get_certificate_trust(&parsed, &fingerprint, &other_port),
+                Response::UnknownCertificate
+            ));
+            let pinned = PinnedCertificate::new(&parsed, &other);
+            known_hosts.trust_once(&host("example.org"), 1966, &pinned);
+        }
+        let known_hosts = KnownHosts::open(&path).unwrap();
+        let list = known_hosts.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].host, example.host);
+        assert_eq!(list[0].port, DEFAULT_GEMINI_PORT);
+        assert_eq!(list[0].certificate.fingerprint, fingerprint);
+        assert_eq!(
+            seconds(list[0].certificate.not_after),
+            parsed.validity().not_after.timestamp() as u64
+        );
+        assert_eq!(
+            list[0].certificate.public_key,
+            Some(Fingerprint::new(
+                crate::x509::subject_public_key_info(&parsed).unwrap()
+            ))
+        );
+        assert!(known_hosts.get(&host("example.org"), 1966).is_none());
+    }
+
+    #[test]
+    fn hosts_and_ports() {
+        let directory = TemporaryDirectory::new();
+        let path = directory.0.join("known_hosts");
+        let pinned = PinnedCertificate {
+            fingerprint: Fingerprint::new(certificate()),
+            public_key: None,
+            names: vec!["-".to_owned(), "with space, and comma".to_owned()],
+            not_before: None,
+            not_after: UNIX_EPOCH + Duration::from_secs(2_000_000_000),
+        };
+        let hosts = [
+            (host("example.com"), 1966),
+            (host("127.0.0.1"), 1965),
+            (host("[::1]"), 1965),
+            (host("[::1]"), 1966),
+        ];
+        {
+            let known_hosts = KnownHosts::open(&path).unwrap();
+            for (host, port) in &hosts {
+                known_hosts.trust_always(host, *port, &pinned).unwrap();
+            }
+            known_hosts.forget(&host("[::1]"), 1966).unwrap();
+            assert!(matches!(
+                known_hosts.forget(&host("[::1]"), 1966),
+                Err(Error::NotFound(_))
+            ));
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        let keys = contents
+            .lines()
+            .skip(1)
+            .map(|line| line.split(' ').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["127.0.0.1", "[::1]", "example.com:1966"]);
+
+        let known_hosts = KnownHosts::open(&path).unwrap();
+        for (host, port) in &hosts[..3] {
+            assert_eq!(known_hosts.get(host, *port).unwrap().certificate, pinned);
+        }
+        assert!(known_hosts.get(&host("example.com"), 1965).is_none());
+    }
+
+    #[test]
+    fn lines_without_later_fields() {
+        let directory = TemporaryDirectory::new();
+        let path = directory.0.join("known_hosts");
+        let fingerprint = Fingerprint::new(b"certificate");
+        let public_key = Fingerprint::new(b"public key");
+        let contents = format!(
+            "example.com {} 1 2 3\nexample.org {} 1 2 3 {}\n",
+            fingerprint, fingerprint, public_key
+        );
+        fs::write(&path, contents).unwrap();
+        let known_hosts = KnownHosts::open(&path).unwrap();
+        let list = known_hosts.list();
+        assert_eq!(list[0].certificate.public_key, None);
+        assert_eq!(list[1].certificate.public_key, Some(public_key));
+        assert_eq!(
+            list[1].certificate.not_after,
+            UNIX_EPOCH + Duration::from_secs(3)
+        );
+        assert_eq!(list[1].certificate.not_before, None);
+        assert!(list[1].certificate.names.is_empty());
+    }
+
+    #[test]
+    fn corrupted_file() {
+        let directory = TemporaryDirectory::new();
+        let path = directory.0.join("known_hosts");
+        for line in &[
+            "example.com SHA-256:00 0 0 0",
+            "example.com/path SHA-256:00 0 0 0",
+            "example.com",
+            "example.com SHA-256:E3:B0:C4:42:98:FC:1C:14:9A:FB:F4:C8:99:6F:B9:24:27:AE:41:E4:64:9B:93:4C:A4:95:99:1B:78:52:B8:55 0 0 0 - - - extra",
+        ] {
+            fs::write(&path, line).unwrap();
+            assert!(matches!(KnownHosts::open(&path), Err(Error::Corrupted(_))));
+        }
+    }
+}
@@ -1,9 +1,26 @@
+pub mod cancel;
 pub mod config;
+mod der;
 pub mod fingerprints;
+pub mod header;
+pub mod host;
+pub mod identity;
+pub mod identity_store;
+pub mod known_hosts;
+pub mod media_type;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod pins;
+pub mod policy;
+pub mod redirect;
 pub mod request;
 pub mod response;
 pub mod status;
+mod storage;
 pub mod tcp;
+#[cfg(test)]
+mod test_support;
+pub mod text;
 pub mod tls;
 pub mod verify;
 pub mod x509;
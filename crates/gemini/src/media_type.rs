@@ -0,0 +1,234 @@
+use crate::response::ProtocolError;
+use std::fmt;
+
+/// Parsed MIME type of a successful response.
+///
+/// Type, subtype, and parameter names are case-insensitive and stored in lowercase.
+/// Parameter values are kept as is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaType {
+    type_: String,
+    subtype: String,
+    parameters: Vec<(String, String)>,
+}
+
+/// Charset assumed for text responses which do not specify it.
+pub const DEFAULT_CHARSET: &str = "utf-8";
+
+impl MediaType {
+    /// Parses META line of a successful response.
+    ///
+    /// Empty string results in the default "text/gemini; charset=utf-8".
+    pub fn parse(meta: &str) -> Result<Self, ProtocolError> {
+        let meta = meta.trim();
+        if meta.is_empty() {
+            return Ok(Self::default());
+        }
+        let mut parts = split_unquoted(meta, ';').into_iter();
+        let essence = parts.next().unwrap_or_default();
+        let (type_, subtype) = essence
+            .split_once('/')
+            .ok_or(ProtocolError::MediaTypeMalformed)?;
+        let (type_, subtype) = (type_.trim(), subtype.trim());
+        if !is_token(type_) || !is_token(subtype) {
+            return Err(ProtocolError::MediaTypeMalformed);
+        }
+        let mut parameters = Vec::new();
+        for parameter in parts {
+            // Be lenient here and skip anything that does not look like a parameter.
+            let (name, value) = match parameter.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            if !is_token(name) {
+                continue;
+            }
+            parameters.push((name.to_ascii_lowercase(), unquote(value)));
+        }
+        Ok(Self {
+            type_: type_.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters,
+        })
+    }
+
+    /// Top-level type, e.g., "text".
+    pub fn type_(&self) -> &str {
+        &self.type_
+    }
+
+    /// Subtype, e.g., "gemini".
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+
+    /// Type and subtype without parameters, e.g., "text/gemini".
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.type_, self.subtype)
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.type_ == "text"
+    }
+
+    /// Value of a parameter, if it is present. Parameter names are case-insensitive.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn parameters(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.parameters
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Character set of the body, UTF-8 if not specified.
+    pub fn charset(&self) -> &str {
+        self.parameter("charset").unwrap_or(DEFAULT_CHARSET)
+    }
+
+    /// Languages of text/gemini documents, as listed in the "lang" parameter.
+    pub fn lang(&self) -> Vec<&str> {
+        self.parameter("lang")
+            .map(|lang| {
+                lang.split(',')
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Default for MediaType {
+    fn default() -> Self {
+        Self {
+            type_: "text".to_owned(),
+            subtype: "gemini".to_owned(),
+            parameters: vec![("charset".to_owned(), DEFAULT_CHARSET.to_owned())],
+        }
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.type_, self.subtype)?;
+        for (name, value) in &self.parameters {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}=\"", name)?;
+                for c in value.chars() {
+                    if c == '"' || c == '\\' {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+                write!(f, "\"")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// RFC 2045, section 5.1
+fn is_token(s: &str) -> bool {
+    const TSPECIALS: &[u8] = b"()<>@,;:\\\"/[]?=";
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_graphic() && !TSPECIALS.contains(&b))
+}
+
+/// Splits the string by `delimiter`, unless it's inside a quoted string.
+fn split_unquoted(s: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    let inner = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => return value.to_owned(),
+    };
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                result.push(next);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_meta_is_gemtext() {
+        let media_type = MediaType::parse("").unwrap();
+        assert_eq!(media_type.essence(), "text/gemini");
+        assert_eq!(media_type.charset(), "utf-8");
+        assert_eq!(media_type.to_string(), "text/gemini; charset=utf-8");
+    }
+
+    #[test]
+    fn parameters() {
+        let media_type =
+            MediaType::parse("Text/Gemini; CHARSET=ISO-8859-1 ;lang=\"en,fr-CA\"").unwrap();
+        assert_eq!(media_type.type_(), "text");
+        assert_eq!(media_type.subtype(), "gemini");
+        assert!(media_type.is_text());
+        assert_eq!(media_type.charset(), "ISO-8859-1");
+        assert_eq!(media_type.parameter("Lang"), Some("en,fr-CA"));
+        assert_eq!(media_type.lang(), vec!["en", "fr-CA"]);
+    }
+
+    #[test]
+    fn charset_defaults_to_utf8() {
+        let media_type = MediaType::parse("text/plain").unwrap();
+        assert_eq!(media_type.charset(), "utf-8");
+        assert!(media_type.lang().is_empty());
+    }
+
+    #[test]
+    fn quoted_values() {
+        let media_type = MediaType::parse(r#"text/plain; title="a; \"b\""; x=y"#).unwrap();
+        assert_eq!(media_type.parameter("title"), Some(r#"a; "b""#));
+        assert_eq!(media_type.parameter("x"), Some("y"));
+        assert_eq!(
+            media_type.to_string(),
+            r#"text/plain; title="a; \"b\""; x=y"#
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(MediaType::parse("text").is_err());
+        assert!(MediaType::parse("text/").is_err());
+        assert!(MediaType::parse("te xt/plain").is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Asynchronous client running on Tokio.
+//!
+//! Requests and responses behave the same as their blocking counterparts in `request`
+//! and `response` modules, and fail with the same errors.
+
+use crate::cancel::CancelToken;
+use crate::config::{Config, IntoConfig};
+use crate::header::{HeaderBuffer, ResponseHeader};
+use crate::identity::Identity;
+use crate::media_type::MediaType;
+use crate::redirect::{Redirect, Redirects};
+use crate::request::{connect_error, identity_for, Error, Options, RequestBuilder};
+use crate::response::{self, BodyLimit, ProtocolError};
+use crate::status::Status;
+use crate::tcp;
+use crate::verify::Endpoint;
+use rustls::TLSError;
+use std::cmp::min;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use url::Url;
+
+pub struct Request;
+
+impl Request {
+    /// Requests `url` with default options.
+    ///
+    /// Besides `Config`, accepts rustls `ClientConfig`, which is used as is.
+    pub async fn perform(url: &str, config: impl IntoConfig) -> Result<Response, Error> {
+        Self::perform_with_options(url, config, &Options::default()).await
+    }
+
+    pub async fn perform_with_options(
+        url: &str,
+        config: impl IntoConfig,
+        options: &Options,
+    ) -> Result<Response, Error> {
+        RequestBuilder::new(url)?
+            .options(options.clone())
+            .perform_async(&config.into_config())
+            .await
+    }
+
+    pub(crate) async fn perform_url(
+        mut url: Url,
+        config: &Arc<Config>,
+        options: &Options,
+    ) -> Result<Response, Error> {
+        let origin = url.clone();
+        let mut redirects = Redirects::new(&options.redirects, &url);
+        loop {
+            let identity = identity_for(&url, &origin, config, options);
+            let mut response =
+                Self::perform_once(url, config, options, identity.as_deref()).await?;
+            let (kind, target) = match response.header().redirect() {
+                Some((kind, target)) => (kind, target.clone()),
+                None => {
+                    response.redirects = redirects.into_hops();
+                    return Ok(response);
+                }
+            };
+            match redirects.follow(response.url(), kind, target)? {
+                Some(target) => url = target,
+                None => {
+                    response.redirects = redirects.into_hops();
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    async fn perform_once(
+        url: Url,
+        config: &Arc<Config>,
+        options: &Options,
+        identity: Option<&Identity>,
+    ) -> Result<Response, Error> {
+        let timeouts = &options.timeouts;
+        let cancel = &options.cancel;
+        let endpoint = Endpoint::from_url(&url)?;
+        let (host, port) = (&endpoint.host, endpoint.port);
+
+        let hostname = host
+            .session_name()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid host name"))?;
+        // Name resolution and connection racing are blocking, keep them off the runtime threads.
+        let stream = {
+            let (host, timeout, token) = (host.clone(), timeouts.connect, cancel.clone());
+            tokio::task::spawn_blocking(move || tcp::connect_to_host(&host, port, timeout, &token))
+                .await
+                .map_err(io::Error::from)?
+                .map_err(|err| connect_error(err, cancel))?
+        };
+        stream.set_nonblocking(true)?;
+        let stream = TcpStream::from_std(stream)?;
+
+        let session_config = config
+            .session_config(&endpoint, identity)
+            .map_err(Error::TLS)?;
+        let connector = TlsConnector::from(session_config);
+        let mut stream = match run(
+            timeouts.handshake,
+            cancel,
+            connector.connect(hostname, stream),
+        )
+        .await
+        {
+            Outcome::Done(result) => result.map_err(request_error)?,
+            Outcome::TimedOut => return Err(Error::HandshakeTimeout),
+            Outcome::Cancelled => return Err(Error::Cancelled),
+        };
+
+        let exchange = async {
+            stream
+                .write_all(url.as_str().as_bytes())
+                .await
+                .map_err(request_error)?;
+            stream.write_all(b"\r\n").await.map_err(request_error)?;
+            stream.flush().await.map_err(request_error)?;
+            Ok::<_, Error>(read_header(&mut stream, &url).await?)
+        };
+        let (header, buffer) = match run(timeouts.first_byte, cancel, exchange).await {
+            Outcome::Done(result) => result?,
+            Outcome::TimedOut => return Err(response::Error::FirstByteTimeout.into()),
+            Outcome::Cancelled => return Err(Error::Cancelled),
+        };
+        Ok(Response {
+            stream,
+            state: State::ReadingData,
+            buffer,
+            position: 0,
+            url,
+            header,
+            redirects: Vec::new(),
+            read_timeout: timeouts.read,
+            cancel: cancel.clone(),
+            limit: BodyLimit::new(options.max_body_size),
+        })
+    }
+}
+
+async fn read_header(
+    stream: &mut TlsStream<TcpStream>,
+    url: &Url,
+) -> Result<(ResponseHeader, Vec<u8>), response::Error> {
+    let mut buffer = HeaderBuffer::new();
+    loop {
+        let read = stream
+            .read(buffer.unfilled()?)
+            .await
+            .map_err(response_error)?;
+        if read == 0 {
+            return Err(ProtocolError::UnexpectedEndOfStream.into());
+        }
+        if buffer.advance(read) {
+            return Ok(buffer.parse(url)?);
+        }
+    }
+}
+
+pub struct Response {
+    stream: TlsStream<TcpStream>,
+    state: State,
+    buffer: Vec<u8>,
+    position: usize,
+    url: Url,
+    header: ResponseHeader,
+    redirects: Vec<Redirect>,
+    read_timeout: Option<Duration>,
+    cancel: CancelToken,
+    limit: BodyLimit,
+}
+
+enum State {
+    ReadingData,
+    Complete,
+    Truncated,
+}
+
+impl Response {
+    /// URL of the requested resource, after following redirects.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Response header, available before the body is read.
+    pub fn header(&self) -> &ResponseHeader {
+        &self.header
+    }
+
+    pub fn status(&self) -> Status {
+        self.header.status()
+    }
+
+    /// MIME type of the body, for successful responses.
+    pub fn media_type(&self) -> Option<&MediaType> {
+        match &self.header {
+            ResponseHeader::Success { media_type, .. } => Some(media_type),
+            _ => None,
+        }
+    }
+
+    /// Whether the body has been read completely and the server confirmed it with close_notify.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, State::Complete)
+    }
+
+    /// Redirects that have been followed to get this response, in order.
+    pub fn redirects(&self) -> &[Redirect] {
+        &self.redirects
+    }
+
+    /// Reads the body, same as the blocking `Response::read`.
+    ///
+    /// The end of the body is reported as `Error::Terminated`, or as `Error::Truncated`
+    /// if the server has not confirmed it with close_notify.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, response::Error> {
+        if self.limit.is_reached() && !buf.is_empty() {
+            // Check whether there is more data beyond the limit.
+            return match self.read_unlimited(&mut [0; 1]).await? {
+                0 => Ok(0),
+                _ => Err(self.limit.error()),
+            };
+        }
+        let len = self.limit.allowed(buf.len());
+        let read = self.read_unlimited(&mut buf[..len]).await?;
+        self.limit.consume(read);
+        Ok(read)
+    }
+
+    async fn read_unlimited(&mut self, buf: &mut [u8]) -> Result<usize, response::Error> {
+        if self.position < self.buffer.len() {
+            let len = min(self.buffer.len() - self.position, buf.len());
+            buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+            self.position += len;
+            return Ok(len);
+        }
+        match self.state {
+            State::ReadingData => {}
+            State::Complete => return Err(response::Error::Terminated),
+            State::Truncated => return Err(response::Error::Truncated),
+        }
+        let read = match run(self.read_timeout, &self.cancel, self.stream.read(buf)).await {
+            Outcome::Done(result) => result.map_err(response_error)?,
+            Outcome::TimedOut => return Err(response::Error::ReadTimeout),
+            Outcome::Cancelled => return Err(response::Error::Cancelled),
+        };
+        if read > 0 || buf.is_empty() {
+            return Ok(read);
+        }
+        // tokio-rustls reports both close_notify and TCP EOF as the end of stream.
+        // The session still knows which one it was: after close_notify it refuses to read.
+        let (_, session) = self.stream.get_mut();
+        match io::Read::read(session, &mut [0; 1]) {
+            Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => {
+                self.state = State::Complete;
+                Err(response::Error::Terminated)
+            }
+            _ => {
+                self.state = State::Truncated;
+                Err(response::Error::Truncated)
+            }
+        }
+    }
+
+    /// Reads the rest of the body, failing if it is truncated.
+    pub async fn read_to_end(&mut self) -> Result<Vec<u8>, response::Error> {
+        let mut body = Vec::new();
+        let mut buffer = vec![0; 8 * 1024];
+        loop {
+            match self.read(&mut buffer).await {
+                Ok(read) => body.extend_from_slice(&buffer[..read]),
+                Err(response::Error::Terminated) => return Ok(body),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+enum Outcome<T> {
+    Done(T),
+    TimedOut,
+    Cancelled,
+}
+
+/// Runs `future` until it completes, `timeout` passes, or `cancel` is cancelled.
+async fn run<F: Future>(
+    timeout: Option<Duration>,
+    cancel: &CancelToken,
+    future: F,
+) -> Outcome<F::Output> {
+    let cancelled = Arc::new(Notify::new());
+    let notify = cancelled.clone();
+    // Notify keeps the permit if the token is cancelled before we start waiting.
+    let _guard = cancel.on_cancel(move || notify.notify_one());
+    let future = async {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+            None => Some(future.await),
+        }
+    };
+    tokio::select! {
+        output = future => match output {
+            Some(output) => Outcome::Done(output),
+            None => Outcome::TimedOut,
+        },
+        _ = cancelled.notified() => Outcome::Cancelled,
+    }
+}
+
+/// tokio-rustls wraps TLS errors into `io::Error`, unwrap them back.
+fn split_tls_error(error: io::Error) -> Result<TLSError, io::Error> {
+    let is_tls = matches!(error.get_ref(), Some(inner) if inner.is::<TLSError>());
+    if !is_tls {
+        return Err(error);
+    }
+    let inner = error.into_inner().expect("checked above");
+    Ok(*inner.downcast::<TLSError>().expect("checked above"))
+}
+
+fn request_error(error: io::Error) -> Error {
+    match split_tls_error(error) {
+        Ok(error) => Error::TLS(error),
+        Err(error) => Error::IO(error),
+    }
+}
+
+fn response_error(error: io::Error) -> response::Error {
+    match split_tls_error(error) {
+        Ok(error) => response::Error::TLS(error),
+        Err(error) => response::Error::IO(error),
+    }
+}
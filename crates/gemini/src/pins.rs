@@ -0,0 +1,324 @@
+//! Static pinning of certificates and public keys for particular hosts.
+//!
+//! Pins are written one per line, with the host, kind of pin, and the fingerprint:
+//!
+//! ```text
+//! # Current key, and the one we're rotating to.
+//! example.com spki SHA-256:E3:B0:C4:42:98:FC:1C:14:9A:FB:F4:C8:99:6F:B9:24:27:AE:41:E4:64:9B:93:4C:A4:95:99:1B:78:52:B8:55
+//! example.com spki SHA-256:6E:34:0B:9C:FF:B3:7A:98:9C:A5:44:E6:BB:78:0A:2C:78:90:1D:3F:B3:37:38:76:85:11:A3:06:17:AF:A0:1D
+//! 192.0.2.1 cert SHA-256:5F:EC:EB:66:FF:C8:6F:38:D9:52:78:6C:6D:69:6C:79:C2:DB:C2:39:DD:4E:91:B4:67:29:D7:3A:27:FB:57:E9
+//! ```
+
+use crate::fingerprints::Fingerprint;
+use crate::host::Host;
+use crate::policy::{Verdict, VerificationPolicy};
+use crate::verify::Endpoint;
+use crate::x509;
+use rustls::{Certificate, TLSError};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use url::Url;
+
+/// Expected fingerprint of the server certificate.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Pin {
+    /// Fingerprint of DER-encoded SubjectPublicKeyInfo. Survives certificate renewal with
+    /// the same key.
+    PublicKey(Fingerprint),
+    /// Fingerprint of the whole certificate.
+    Certificate(Fingerprint),
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::PublicKey(fingerprint) => write!(f, "spki {}", fingerprint),
+            Pin::Certificate(fingerprint) => write!(f, "cert {}", fingerprint),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParsePinError {
+    #[error("invalid pin")]
+    InvalidPin,
+    #[error("invalid pin on line {0}")]
+    InvalidLine(usize),
+}
+
+impl FromStr for Pin {
+    type Err = ParsePinError;
+
+    /// Parses pin in the same format as it is displayed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (kind, fingerprint) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(kind), Some(fingerprint), None) => (kind, fingerprint),
+            _ => return Err(ParsePinError::InvalidPin),
+        };
+        let fingerprint = fingerprint.parse().map_err(|_| ParsePinError::InvalidPin)?;
+        match kind {
+            "spki" => Ok(Pin::PublicKey(fingerprint)),
+            "cert" => Ok(Pin::Certificate(fingerprint)),
+            _ => Err(ParsePinError::InvalidPin),
+        }
+    }
+}
+
+/// Verification policy accepting only pinned certificates for pinned hosts, on any port.
+/// Abstains from decision about other hosts.
+///
+/// Certificates matching any of the host's pins are accepted, as long as they are valid
+/// for the host. Others are rejected, even if some other policy would trust them.
+#[derive(Clone, Debug, Default)]
+pub struct StaticPins {
+    hosts: HashMap<Host, Vec<Pin>>,
+}
+
+impl StaticPins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a pin for the host. Hosts may have several pins, for key rotation.
+    pub fn pin(mut self, host: Host, pin: Pin) -> Self {
+        let pins = self.hosts.entry(host).or_default();
+        if !pins.contains(&pin) {
+            pins.push(pin);
+        }
+        self
+    }
+
+    /// Pins of the host, in order of addition.
+    pub fn pins(&self, host: &Host) -> &[Pin] {
+        self.hosts.get(host).map_or(&[], |pins| pins)
+    }
+}
+
+impl FromStr for StaticPins {
+    type Err = ParsePinError;
+
+    /// Parses pins from lines described in module documentation.
+    /// Empty lines and comments starting with '#' are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pins = StaticPins::new();
+        for (index, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || ParsePinError::InvalidLine(index + 1);
+            let (host, pin) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let url = Url::parse(&format!("gemini://{}/", host)).map_err(|_| invalid())?;
+            // Pins are not scoped to ports, insist on the host alone.
+            if url.port().is_some() {
+                return Err(invalid());
+            }
+            let host = Host::from_url(&url).map_err(|_| invalid())?;
+            pins = pins.pin(host, pin.parse().map_err(|_| invalid())?);
+        }
+        Ok(pins)
+    }
+}
+
+impl VerificationPolicy for StaticPins {
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        let pins = match self.hosts.get(&endpoint.host) {
+            Some(pins) => pins,
+            None => return Verdict::Abstain,
+        };
+        let certificate = match presented_certs.first() {
+            Some(certificate) => certificate,
+            None => return Verdict::Reject(TLSError::NoCertificatesPresented),
+        };
+        let fingerprint = Fingerprint::new(certificate);
+        let certificate = match x509_parser::parse_x509_certificate(certificate.as_ref()) {
+            Ok((_, certificate)) => certificate,
+            Err(_) => return Verdict::Reject(TLSError::WebPKIError(webpki::Error::BadDER)),
+        };
+        let public_key = x509::subject_public_key_info(&certificate).map(Fingerprint::new);
+        let matches = |pin: &Pin| match pin {
+            Pin::Certificate(pinned) => *pinned == fingerprint,
+            Pin::PublicKey(pinned) => public_key.as_ref() == Some(pinned),
+        };
+        if pins.iter().any(matches) {
+            // Pinned certificate may still be expired or issued for other names.
+            return match x509::check_certificate_for_host(&certificate, &endpoint.host) {
+                Ok(()) => Verdict::Accept,
+                Err(err) => Verdict::Reject(TLSError::WebPKIError(err)),
+            };
+        }
+        let pins = pins.iter().map(Pin::to_string).collect::<Vec<_>>();
+        Verdict::Reject(TLSError::General(format!(
+            "certificate of {} does not match pinned {}",
+            endpoint.host,
+            pins.join(", ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::known_hosts::KnownHosts;
+    use crate::policy::FirstMatch;
+    use crate::storage::tests::TemporaryDirectory;
+    use crate::test_support::{certificate, endpoint, self_signed};
+    use crate::verify::{
+        CertificateDetails, CertificateTrustCache, CertificateVerifier, TrustDecision,
+        VerificationDelegate, VerificationIssue,
+    };
+    use x509_parser::certificate::X509Certificate;
+
+    struct NeverAsked;
+
+    impl VerificationDelegate for NeverAsked {
+        fn decide_certificate_trust(
+            &self,
+            _certificate: &X509Certificate<'_>,
+            _details: &CertificateDetails,
+            _endpoint: &Endpoint,
+            _issue: VerificationIssue,
+        ) -> TrustDecision {
+            panic!("pinned hosts must not be prompted for");
+        }
+    }
+
+    fn spki(key: &[u8]) -> Pin {
+        let key = rcgen::KeyPair::from_der(key).unwrap();
+        Pin::PublicKey(Fingerprint::new(key.public_key_der()))
+    }
+
+    #[test]
+    fn pinned_hosts() {
+        let (old, old_key) = certificate(None);
+        let (renewed, _) = certificate(Some(&old_key));
+        let (new, new_key) = certificate(None);
+        let (other, _) = certificate(None);
+        let (old_spki, new_spki) = (spki(&old_key), spki(&new_key));
+        let old_cert = Pin::Certificate(Fingerprint::new(&old));
+        let host = Host::Domain("example.com".to_owned());
+        let pins = StaticPins::new()
+            .pin(host.clone(), old_spki.clone())
+            .pin(host.clone(), new_spki);
+        assert_eq!(pins.pins(&host).len(), 2);
+
+        let directory = TemporaryDirectory::new();
+        let known_hosts = KnownHosts::open(directory.0.join("known_hosts")).unwrap();
+        let policy = FirstMatch::new()
+            .with(pins)
+            .with(CertificateVerifier::new(NeverAsked, known_hosts));
+        let example = endpoint("gemini://example.com:1966/");
+        for certificate in &[old.clone(), renewed, new] {
+            assert_eq!(
+                policy.verify(std::slice::from_ref(certificate), &example),
+                Verdict::Accept
+            );
+        }
+        match policy.verify(std::slice::from_ref(&other), &example) {
+            Verdict::Reject(TLSError::General(message)) => {
+                assert!(message.contains(&old_spki.to_string()));
+            }
+            verdict => panic!("unexpected verdict: {:?}", verdict),
+        }
+
+        let pins = StaticPins::new().pin(host, old_cert);
+        assert_eq!(
+            pins.verify(std::slice::from_ref(&old), &example),
+            Verdict::Accept
+        );
+        assert!(matches!(
+            pins.verify(std::slice::from_ref(&other), &example),
+            Verdict::Reject(_)
+        ));
+        assert_eq!(
+            pins.verify(
+                std::slice::from_ref(&other),
+                &endpoint("gemini://example.org/")
+            ),
+            Verdict::Abstain
+        );
+    }
+
+    #[test]
+    fn pins_override_trust_cache() {
+        let (pinned, key) = certificate(None);
+        let (trusted, _) = certificate(None);
+        let example = endpoint("gemini://example.com/");
+        let directory = TemporaryDirectory::new();
+        let known_hosts = KnownHosts::open(directory.0.join("known_hosts")).unwrap();
+        let (_, parsed) = x509_parser::parse_x509_certificate(&trusted.0).unwrap();
+        known_hosts.trust_certificate_always(&parsed, &Fingerprint::new(&trusted), &example);
+
+        let pins = StaticPins::new().pin(Host::Domain("example.com".to_owned()), spki(&key));
+        let verifier = CertificateVerifier::new(NeverAsked, known_hosts).static_pins(pins);
+        assert!(matches!(
+            verifier.verify(std::slice::from_ref(&trusted), &example),
+            Verdict::Reject(_)
+        ));
+        assert_eq!(
+            verifier.verify(std::slice::from_ref(&pinned), &example),
+            Verdict::Accept
+        );
+    }
+
+    #[test]
+    fn pinned_certificates_are_validated() {
+        let host = Host::Domain("example.com".to_owned());
+        let example = endpoint("gemini://example.com/");
+
+        let mut params = rcgen::CertificateParams::new(vec!["example.com".to_owned()]);
+        params.not_after = rcgen::date_time_ymd(2000, 1, 1);
+        let (expired, _) = self_signed(params);
+        let pins =
+            StaticPins::new().pin(host.clone(), Pin::Certificate(Fingerprint::new(&expired)));
+        assert_eq!(
+            pins.verify(std::slice::from_ref(&expired), &example),
+            Verdict::Reject(TLSError::WebPKIError(webpki::Error::CertExpired))
+        );
+
+        let params = rcgen::CertificateParams::new(vec!["example.org".to_owned()]);
+        let (other_name, _) = self_signed(params);
+        let pins = StaticPins::new().pin(host, Pin::Certificate(Fingerprint::new(&other_name)));
+        assert!(matches!(
+            pins.verify(std::slice::from_ref(&other_name), &example),
+            Verdict::Reject(TLSError::WebPKIError(_))
+        ));
+    }
+
+    #[test]
+    fn configuration() {
+        let spki = Pin::PublicKey(Fingerprint::new(b"key"));
+        let cert = Pin::Certificate(Fingerprint::new(b"certificate"));
+        let text = format!(
+            "# Comment\n\nexample.com {}\nexample.com {}\n[::1]\t {}\n",
+            spki, cert, cert
+        );
+        let pins = text.parse::<StaticPins>().unwrap();
+        let example = Host::Domain("example.com".to_owned());
+        assert_eq!(pins.pins(&example), [spki.clone(), cert.clone()]);
+        assert_eq!(
+            cert.to_string()
+                .replace(' ', " \t ")
+                .parse::<Pin>()
+                .unwrap(),
+            cert
+        );
+        assert_eq!(pins.pins(&Host::Ip("::1".parse().unwrap())), [cert]);
+
+        let invalid = [
+            format!("example.com:1965 {}", spki),
+            format!("example.com {} extra", spki),
+            "example.com spki SHA-256:00".to_owned(),
+            "example.com".to_owned(),
+        ];
+        for line in &invalid {
+            let text = format!("# Comment\n{}\n", line);
+            assert!(matches!(
+                text.parse::<StaticPins>(),
+                Err(ParsePinError::InvalidLine(2))
+            ));
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Verification policies and their combinations.
+//!
+//! Policies accept or reject certificates presented by servers, or abstain from the decision
+//! leaving it to other policies. `CertificateVerifier` is the TOFU policy. Others can be combined
+//! with it, for example:
+//!
+//! ```no_run
+//! # use cartouche_gemini::config;
+//! # use cartouche_gemini::host::Host;
+//! # use cartouche_gemini::policy::{AcceptAll, CertificateAuthorities, FirstMatch, HostOverrides};
+//! # use cartouche_gemini::verify::{self, CertificateVerifier};
+//! # use std::sync::Arc;
+//! # fn example(tofu: CertificateVerifier<impl verify::VerificationDelegate + 'static,
+//! #                                      impl verify::CertificateTrustCache + 'static>) {
+//! let dev = HostOverrides::new().endpoint(Host::Domain("localhost".into()), 1965, AcceptAll);
+//! let ca = CertificateAuthorities::new(verify::load_roots("roots.pem").unwrap());
+//! let policy = FirstMatch::new().with(dev).with(ca).with(tofu);
+//! let config = config::new_shared_config(Arc::new(policy), None);
+//! # }
+//! ```
+
+use crate::host::Host;
+use crate::verify::{self, Endpoint, ServerVerifier};
+use rustls::{Certificate, RootCertStore, ServerCertVerified, TLSError};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Decides whether to trust certificates presented by servers.
+///
+/// Policies are used as `ServerVerifier`, which rejects certificates if the policy abstains.
+pub trait VerificationPolicy: Send + Sync {
+    /// Verifies `presented_certs`, the first of which is the server's certificate.
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Accept,
+    Reject(TLSError),
+    /// The policy does not apply, let others decide.
+    Abstain,
+}
+
+impl From<Result<ServerCertVerified, TLSError>> for Verdict {
+    fn from(result: Result<ServerCertVerified, TLSError>) -> Self {
+        match result {
+            Ok(_) => Verdict::Accept,
+            Err(err) => Verdict::Reject(err),
+        }
+    }
+}
+
+impl<P: VerificationPolicy + ?Sized> ServerVerifier for P {
+    fn verify_server_cert(
+        &self,
+        presented_certs: &[Certificate],
+        endpoint: &Endpoint,
+    ) -> Result<ServerCertVerified, TLSError> {
+        match self.verify(presented_certs, endpoint) {
+            Verdict::Accept => Ok(ServerCertVerified::assertion()),
+            Verdict::Reject(err) => Err(err),
+            Verdict::Abstain => Err(TLSError::General(
+                "no verification policy applies".to_owned(),
+            )),
+        }
+    }
+}
+
+impl<P: VerificationPolicy + ?Sized> VerificationPolicy for Box<P> {
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        (**self).verify(presented_certs, endpoint)
+    }
+}
+
+impl<P: VerificationPolicy + ?Sized> VerificationPolicy for Arc<P> {
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        (**self).verify(presented_certs, endpoint)
+    }
+}
+
+/// Accepts any certificate. Meant for local development servers.
+pub struct AcceptAll;
+
+impl VerificationPolicy for AcceptAll {
+    fn verify(&self, _presented_certs: &[Certificate], _endpoint: &Endpoint) -> Verdict {
+        Verdict::Accept
+    }
+}
+
+/// Accepts certificate chains issued for the host by trusted certificate authorities.
+/// Abstains from decision about other chains.
+pub struct CertificateAuthorities {
+    roots: RootCertStore,
+}
+
+impl CertificateAuthorities {
+    pub fn new(roots: RootCertStore) -> Self {
+        Self { roots }
+    }
+}
+
+impl VerificationPolicy for CertificateAuthorities {
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        if verify::has_trusted_chain(&self.roots, presented_certs, &endpoint.host) {
+            Verdict::Accept
+        } else {
+            Verdict::Abstain
+        }
+    }
+}
+
+/// Takes the verdict of the first policy which does not abstain, in order.
+#[derive(Default)]
+pub struct FirstMatch {
+    policies: Vec<Box<dyn VerificationPolicy>>,
+}
+
+impl FirstMatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, policy: impl VerificationPolicy + 'static) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
+}
+
+impl VerificationPolicy for FirstMatch {
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        for policy in &self.policies {
+            match policy.verify(presented_certs, endpoint) {
+                Verdict::Abstain => continue,
+                verdict => return verdict,
+            }
+        }
+        Verdict::Abstain
+    }
+}
+
+/// Accepts certificates only if every policy accepts them.
+///
+/// Policies are asked in order until one rejects or abstains, which is the verdict then.
+/// Put policies which may ask the user last, so that they are not asked in vain.
+#[derive(Default)]
+pub struct AllPass {
+    policies: Vec<Box<dyn VerificationPolicy>>,
+}
+
+impl AllPass {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, policy: impl VerificationPolicy + 'static) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
+}
+
+impl VerificationPolicy for AllPass {
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        if self.policies.is_empty() {
+            return Verdict::Abstain;
+        }
+        for policy in &self.policies {
+            match policy.verify(presented_certs, endpoint) {
+                Verdict::Accept => continue,
+                verdict => return verdict,
+            }
+        }
+        Verdict::Accept
+    }
+}
+
+/// Policies for particular hosts. Abstains from decision about other hosts.
+#[derive(Default)]
+pub struct HostOverrides {
+    overrides: HashMap<(Host, Option<u16>), Box<dyn VerificationPolicy>>,
+}
+
+impl HostOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `policy` for all ports of the host, unless overridden for a particular port.
+    pub fn host(mut self, host: Host, policy: impl VerificationPolicy + 'static) -> Self {
+        self.overrides.insert((host, None), Box::new(policy));
+        self
+    }
+
+    /// Uses `policy` for the port of the host.
+    pub fn endpoint(
+        mut self,
+        host: Host,
+        port: u16,
+        policy: impl VerificationPolicy + 'static,
+    ) -> Self {
+        self.overrides.insert((host, Some(port)), Box::new(policy));
+        self
+    }
+}
+
+impl VerificationPolicy for HostOverrides {
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        let host = endpoint.host.clone();
+        let policy = self
+            .overrides
+            .get(&(host.clone(), Some(endpoint.port)))
+            .or_else(|| self.overrides.get(&(host, None)));
+        match policy {
+            Some(policy) => policy.verify(presented_certs, endpoint),
+            None => Verdict::Abstain,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use url::Url;
+
+    /// Always gives the same verdict, counting how many times it's been asked.
+    struct Fixed(Verdict, Arc<AtomicUsize>);
+
+    impl VerificationPolicy for Fixed {
+        fn verify(&self, _presented_certs: &[Certificate], _endpoint: &Endpoint) -> Verdict {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            self.0.clone()
+        }
+    }
+
+    fn reject() -> Verdict {
+        Verdict::Reject(TLSError::General("rejected".to_owned()))
+    }
+
+    fn verify(policy: &impl VerificationPolicy, url: &str) -> Verdict {
+        let endpoint = Endpoint::from_url(&Url::parse(url).unwrap()).unwrap();
+        policy.verify(&[], &endpoint)
+    }
+
+    #[test]
+    fn combinators() {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let fixed = |verdict: Verdict| Fixed(verdict, asked.clone());
+        let url = "gemini://example.com/";
+
+        let first = FirstMatch::new()
+            .with(fixed(Verdict::Abstain))
+            .with(fixed(reject()))
+            .with(fixed(Verdict::Accept));
+        assert_eq!(verify(&first, url), reject());
+        assert_eq!(asked.swap(0, Ordering::SeqCst), 2);
+        assert_eq!(verify(&FirstMatch::new(), url), Verdict::Abstain);
+
+        let all = AllPass::new()
+            .with(fixed(Verdict::Accept))
+            .with(fixed(Verdict::Abstain))
+            .with(fixed(reject()));
+        assert_eq!(verify(&all, url), Verdict::Abstain);
+        assert_eq!(asked.swap(0, Ordering::SeqCst), 2);
+        let all = AllPass::new()
+            .with(fixed(Verdict::Accept))
+            .with(fixed(Verdict::Accept));
+        assert_eq!(verify(&all, url), Verdict::Accept);
+        assert_eq!(verify(&AllPass::new(), url), Verdict::Abstain);
+
+        // Abstaining policy rejects certificates when used as a verifier.
+        let endpoint = Endpoint::from_url(&Url::parse(url).unwrap()).unwrap();
+        assert!(FirstMatch::new()
+            .verify_server_cert(&[], &endpoint)
+            .is_err());
+        assert!(AcceptAll.verify_server_cert(&[], &endpoint).is_ok());
+    }
+
+    #[test]
+    fn host_overrides() {
+        let host = |name: &str| Host::Domain(name.to_owned());
+        let asked = Arc::new(AtomicUsize::new(0));
+        let overrides = HostOverrides::new()
+            .host(host("example.com"), Fixed(reject(), asked.clone()))
+            .endpoint(host("example.com"), 1966, AcceptAll);
+        assert_eq!(verify(&overrides, "gemini://example.com/"), reject());
+        assert_eq!(verify(&overrides, "gemini://example.com:1967/"), reject());
+        assert_eq!(
+            verify(&overrides, "gemini://example.com:1966/"),
+            Verdict::Accept
+        );
+        assert_eq!(
+            verify(&overrides, "gemini://example.org/"),
+            Verdict::Abstain
+        );
+        assert_eq!(asked.load(Ordering::SeqCst), 2);
+    }
+}
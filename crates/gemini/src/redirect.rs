@@ -0,0 +1,293 @@
+use crate::request::{normalize_url, Error, DEFAULT_GEMINI_PORT};
+use std::sync::Arc;
+use url::Url;
+
+/// Default limit on redirect hops, as recommended by the specification.
+pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+
+/// Describes which redirects are followed automatically.
+///
+/// Redirects within the same host and port are followed up to the hop limit.
+/// Redirects to other hosts are followed only if the delegate allows it.
+/// Redirects to other schemes are never followed, the caller receives them as is.
+#[derive(Clone)]
+pub struct RedirectPolicy {
+    max_redirects: usize,
+    delegate: Option<Arc<dyn RedirectDelegate>>,
+}
+
+impl RedirectPolicy {
+    /// Follow at most `max_redirects` hops, failing with `Error::TooManyRedirects` after that.
+    ///
+    /// Zero limit means that redirects are not followed and returned to the caller.
+    pub fn new(max_redirects: usize) -> Self {
+        Self {
+            max_redirects,
+            delegate: None,
+        }
+    }
+
+    /// Do not follow any redirects.
+    pub fn none() -> Self {
+        Self::new(0)
+    }
+
+    /// Ask `delegate` about redirects to other hosts.
+    pub fn with_delegate(mut self, delegate: Arc<dyn RedirectDelegate>) -> Self {
+        self.delegate = Some(delegate);
+        self
+    }
+
+    pub fn max_redirects(&self) -> usize {
+        self.max_redirects
+    }
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_REDIRECTS)
+    }
+}
+
+/// A redirect from one URL to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redirect {
+    pub from: Url,
+    pub to: Url,
+    pub kind: RedirectKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// Status 30, the client should keep using the original URL.
+    Temporary,
+    /// Status 31, the client may remember the new URL instead of the original one.
+    Permanent,
+}
+
+pub trait RedirectDelegate: Send + Sync {
+    /// Decide whether a redirect to another host should be followed.
+    fn decide_redirect(&self, redirect: &Redirect) -> RedirectDecision;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectDecision {
+    Follow,
+    Stop,
+}
+
+/// Keeps track of the redirect chain of a single request.
+pub(crate) struct Redirects<'a> {
+    policy: &'a RedirectPolicy,
+    visited: Vec<Url>,
+    hops: Vec<Redirect>,
+}
+
+impl<'a> Redirects<'a> {
+    pub(crate) fn new(policy: &'a RedirectPolicy, url: &Url) -> Self {
+        Self {
+            policy,
+            visited: vec![url.clone()],
+            hops: Vec::new(),
+        }
+    }
+
+    /// Decides whether a redirect from `from` to `to` should be followed.
+    ///
+    /// Returns normalized `to`, or `None` if the redirect should be returned to the caller instead.
+    pub(crate) fn follow(
+        &mut self,
+        from: &Url,
+        kind: RedirectKind,
+        to: Url,
+    ) -> Result<Option<Url>, Error> {
+        if to.scheme() != from.scheme() {
+            return Ok(None);
+        }
+        if self.policy.max_redirects == 0 {
+            return Ok(None);
+        }
+        // Otherwise fragments or explicit default port would hide a loop.
+        let to = normalize_url(to)?;
+        if self.visited.contains(&to) {
+            return Err(Error::RedirectLoop(to));
+        }
+        if self.hops.len() >= self.policy.max_redirects {
+            return Err(Error::TooManyRedirects(to));
+        }
+
+        let redirect = Redirect {
+            from: from.clone(),
+            to,
+            kind,
+        };
+        if !same_origin(&redirect.from, &redirect.to) {
+            let decision = match &self.policy.delegate {
+                Some(delegate) => delegate.decide_redirect(&redirect),
+                None => RedirectDecision::Stop,
+            };
+            if decision == RedirectDecision::Stop {
+                return Ok(None);
+            }
+        }
+
+        self.visited.push(redirect.to.clone());
+        self.hops.push(redirect);
+        Ok(self.visited.last().cloned())
+    }
+
+    /// Redirects that have been followed, in order.
+    pub(crate) fn into_hops(self) -> Vec<Redirect> {
+        self.hops
+    }
+}
+
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.host() == b.host() && port(a) == port(b)
+}
+
+fn port(url: &Url) -> Option<u16> {
+    match url.scheme() {
+        "gemini" => Some(url.port().unwrap_or(DEFAULT_GEMINI_PORT)),
+        _ => url.port_or_known_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AllowAll;
+
+    impl RedirectDelegate for AllowAll {
+        fn decide_redirect(&self, _redirect: &Redirect) -> RedirectDecision {
+            RedirectDecision::Follow
+        }
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn records_followed_hops() {
+        let policy = RedirectPolicy::default();
+        let start = url("gemini://example.com/docs");
+        let mut redirects = Redirects::new(&policy, &start);
+        let next = redirects
+            .follow(
+                &start,
+                RedirectKind::Permanent,
+                url("gemini://example.com/docs/"),
+            )
+            .unwrap();
+        assert_eq!(next, Some(url("gemini://example.com/docs/")));
+        let hops = redirects.into_hops();
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].kind, RedirectKind::Permanent);
+    }
+
+    #[test]
+    fn detects_loops() {
+        let policy = RedirectPolicy::default();
+        let a = url("gemini://example.com/a");
+        let b = url("gemini://example.com/b");
+        let mut redirects = Redirects::new(&policy, &a);
+        assert!(redirects
+            .follow(&a, RedirectKind::Temporary, b.clone())
+            .unwrap()
+            .is_some());
+        assert!(matches!(
+            redirects.follow(&b, RedirectKind::Temporary, a.clone()),
+            Err(Error::RedirectLoop(target)) if target == a
+        ));
+    }
+
+    #[test]
+    fn detects_loops_through_equivalent_urls() {
+        let policy = RedirectPolicy::default();
+        let a = url("gemini://example.com/a");
+        let b = url("gemini://example.com/b");
+        let mut redirects = Redirects::new(&policy, &a);
+        assert_eq!(
+            redirects
+                .follow(&a, RedirectKind::Temporary, url("gemini://example.com/b#1"))
+                .unwrap(),
+            Some(b.clone())
+        );
+        for target in &["gemini://example.com/a#1", "gemini://example.com:1965/a"] {
+            assert!(matches!(
+                redirects.follow(&b, RedirectKind::Temporary, url(target)),
+                Err(Error::RedirectLoop(target)) if target == a
+            ));
+        }
+    }
+
+    #[test]
+    fn default_port_is_same_origin() {
+        let policy = RedirectPolicy::default();
+        let from = url("gemini://example.com:1965/");
+        let mut redirects = Redirects::new(&policy, &from);
+        let next = redirects.follow(
+            &from,
+            RedirectKind::Temporary,
+            url("gemini://example.com/a"),
+        );
+        assert_eq!(next.unwrap(), Some(url("gemini://example.com/a")));
+        let next = redirects.follow(
+            &from,
+            RedirectKind::Temporary,
+            url("gemini://example.com:1966/"),
+        );
+        assert_eq!(next.unwrap(), None);
+    }
+
+    #[test]
+    fn limits_hops() {
+        let policy = RedirectPolicy::new(2);
+        let mut from = url("gemini://example.com/0");
+        let mut redirects = Redirects::new(&policy, &from);
+        for i in 1..=2 {
+            from = redirects
+                .follow(
+                    &from,
+                    RedirectKind::Temporary,
+                    from.join(&i.to_string()).unwrap(),
+                )
+                .unwrap()
+                .unwrap();
+        }
+        assert!(matches!(
+            redirects.follow(
+                &from,
+                RedirectKind::Temporary,
+                url("gemini://example.com/3")
+            ),
+            Err(Error::TooManyRedirects(_))
+        ));
+    }
+
+    #[test]
+    fn asks_delegate_about_other_hosts() {
+        let from = url("gemini://example.com/");
+
+        let policy = RedirectPolicy::default();
+        let mut redirects = Redirects::new(&policy, &from);
+        let next = redirects.follow(&from, RedirectKind::Temporary, url("gemini://example.org/"));
+        assert_eq!(next.unwrap(), None);
+
+        let policy = RedirectPolicy::default().with_delegate(Arc::new(AllowAll));
+        let mut redirects = Redirects::new(&policy, &from);
+        let next = redirects.follow(&from, RedirectKind::Temporary, url("gemini://example.org/"));
+        assert_eq!(next.unwrap(), Some(url("gemini://example.org/")));
+    }
+
+    #[test]
+    fn does_not_follow_other_schemes() {
+        let policy = RedirectPolicy::default().with_delegate(Arc::new(AllowAll));
+        let from = url("gemini://example.com/");
+        let mut redirects = Redirects::new(&policy, &from);
+        let next = redirects.follow(&from, RedirectKind::Temporary, url("https://example.com/"));
+        assert_eq!(next.unwrap(), None);
+    }
+}
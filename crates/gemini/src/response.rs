@@ -1,35 +1,91 @@
+use crate::header::{HeaderBuffer, ResponseHeader};
+use crate::media_type::MediaType;
+use crate::redirect::Redirect;
 use crate::status::Status;
 use crate::tls;
 use crate::tls::Stream;
 use std::cmp::min;
 use std::io;
-use std::str;
-use std::convert::TryInto;
+use std::time::Duration;
+use url::Url;
 
 pub struct Response {
     stream: Stream,
     state: State,
     buffer: Vec<u8>,
-    status: Status,
-    meta: String,
+    position: usize,
+    url: Url,
+    header: ResponseHeader,
+    redirects: Vec<Redirect>,
+    limit: BodyLimit,
 }
 
 enum State {
-    ReadingHeader,
     ReadingData,
     Complete,
+    Truncated,
 }
 
 impl Response {
-    pub(crate) fn new(stream: Stream) -> Self {
-        Self {
+    /// Reads response header from the stream, leaving the body to be read later.
+    pub(crate) fn read_from(mut stream: Stream, url: Url) -> Result<Self, Error> {
+        let (header, buffer) = read_header(&mut stream, &url)?;
+        Ok(Self {
             stream,
-            state: State::ReadingHeader,
-            buffer: Vec::new(),
-            status: Status::BadRequest,
-            meta: String::new(),
+            state: State::ReadingData,
+            buffer,
+            position: 0,
+            url,
+            header,
+            redirects: Vec::new(),
+            limit: BodyLimit::default(),
+        })
+    }
+
+    /// URL of the requested resource, after following redirects.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Response header, available before the body is read.
+    pub fn header(&self) -> &ResponseHeader {
+        &self.header
+    }
+
+    pub fn status(&self) -> Status {
+        self.header.status()
+    }
+
+    /// MIME type of the body, for successful responses.
+    pub fn media_type(&self) -> Option<&MediaType> {
+        match &self.header {
+            ResponseHeader::Success { media_type, .. } => Some(media_type),
+            _ => None,
         }
     }
+
+    /// Whether the body has been read completely and the server confirmed it with close_notify.
+    pub fn is_complete(&self) -> bool {
+        matches!(self.state, State::Complete)
+    }
+
+    /// Redirects that have been followed to get this response, in order.
+    pub fn redirects(&self) -> &[Redirect] {
+        &self.redirects
+    }
+
+    pub(crate) fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.stream.set_deadline(None);
+        self.stream.set_idle_timeout(timeout);
+    }
+
+    pub(crate) fn set_max_body_size(&mut self, max: Option<usize>) {
+        self.limit = BodyLimit::new(max);
+    }
+
+    pub(crate) fn set_redirects(&mut self, redirects: Vec<Redirect>) {
+        self.redirects = redirects;
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -44,6 +100,16 @@ pub enum Error {
     Interrupted,
     #[error("connection terminated")]
     Terminated,
+    #[error("connection closed before the response was complete")]
+    Truncated,
+    #[error("response header timed out")]
+    FirstByteTimeout,
+    #[error("response body timed out")]
+    ReadTimeout,
+    #[error("response cancelled")]
+    Cancelled,
+    #[error("response body is larger than {0} bytes")]
+    BodyTooLarge(usize),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -58,107 +124,160 @@ pub enum ProtocolError {
     HeaderMalformed,
     #[error("unknown status code")]
     UnknownStatus,
+    #[error("malformed media type")]
+    MediaTypeMalformed,
+    #[error("invalid redirect target: {0}")]
+    InvalidRedirect(url::ParseError),
+}
+
+/// Keeps track of the body size, if it is limited.
+#[derive(Default)]
+pub(crate) struct BodyLimit {
+    max: Option<usize>,
+    read: usize,
+}
+
+impl BodyLimit {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        Self { max, read: 0 }
+    }
+
+    /// How many of `len` bytes may be read without exceeding the limit.
+    pub(crate) fn allowed(&self, len: usize) -> usize {
+        match self.max {
+            Some(max) => min(len, max.saturating_sub(self.read)),
+            None => len,
+        }
+    }
+
+    /// Whether the body may not get any larger. Any more data is an error then.
+    pub(crate) fn is_reached(&self) -> bool {
+        matches!(self.max, Some(max) if self.read >= max)
+    }
+
+    pub(crate) fn consume(&mut self, read: usize) {
+        self.read += read;
+    }
+
+    pub(crate) fn error(&self) -> Error {
+        Error::BodyTooLarge(self.max.unwrap_or_default())
+    }
 }
 
 impl Response {
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
-        match self.state {
-            State::ReadingHeader => {
-                let (mut buffer, line_ending) = read_header(&mut self.stream)?;
-                let (header, data) = (&buffer[..line_ending], &buffer[line_ending + 2..]);
-                let (status, meta) = parse_header(header)?;
-                self.status = status;
-                self.meta = meta.to_owned();
-
-                match status {
-                    Status::Success => {
-                        // TODO:
-                    }
-                    _ => unimplemented!("status {:?} is not supported yet", status),
-                }
+        if self.limit.is_reached() && !buf.is_empty() {
+            // Check whether there is more data beyond the limit.
+            return match self.read_unlimited(&mut [0; 1])? {
+                0 => Ok(0),
+                _ => Err(self.limit.error()),
+            };
+        }
+        let len = self.limit.allowed(buf.len());
+        let read = self.read_unlimited(&mut buf[..len])?;
+        self.limit.consume(read);
+        Ok(read)
+    }
 
-                if data.is_empty() {
-                    self.state = State::ReadingData;
-                    return self.read(buf);
-                }
+    fn read_unlimited(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.position < self.buffer.len() {
+            let len = min(self.buffer.len() - self.position, buf.len());
+            buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+            self.position += len;
+            return Ok(len);
+        }
+        read_body(&mut self.stream, &mut self.state, buf)
+    }
+}
+
+const BUFFER_SIZE: usize = 8 * 1024;
 
-                let len = min(data.len(), buf.len());
-                buf[..len].copy_from_slice(&data[..len]);
-                self.buffer = buffer.split_off(len);
-                self.state = State::ReadingData;
-                Ok(len)
+/// Reads the body, mapping `Error::Terminated` to EOF and retrying on `Error::Interrupted`.
+///
+/// Other errors are converted into `io::Error` which can be downcast into `response::Error`.
+/// Note that `Error::Truncated` is an error of `io::ErrorKind::UnexpectedEof` kind, not an EOF.
+impl io::Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match Response::read(self, buf) {
+                Ok(read) => return Ok(read),
+                Err(Error::Terminated) => return Ok(0),
+                Err(Error::Interrupted) => continue,
+                Err(error) => return Err(error.into()),
             }
-            State::ReadingData => {
-                if !self.buffer.is_empty() {
-                    let len = min(self.buffer.len(), buf.len());
-                    buf[..len].copy_from_slice(&self.buffer[..len]);
-                    self.buffer = self.buffer.split_off(len);
-                    return Ok(len);
-                }
-                match self.stream.read(buf) {
-                    Ok(read) => Ok(read),
-                    Err(tls::Error::Terminated) => {
-                        self.state = State::Complete;
-                        Err(Error::Terminated)
+        }
+    }
+}
+
+impl io::BufRead for Response {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.buffer.len() {
+            self.buffer.resize(BUFFER_SIZE, 0);
+            self.position = 0;
+            let read = loop {
+                match read_body(&mut self.stream, &mut self.state, &mut self.buffer) {
+                    Ok(read) => break read,
+                    Err(Error::Terminated) => break 0,
+                    Err(Error::Interrupted) => continue,
+                    Err(error) => {
+                        self.buffer.clear();
+                        return Err(error.into());
                     }
-                    Err(other) => Err(other.into()),
                 }
-            }
-            State::Complete => Err(Error::Terminated),
+            };
+            self.buffer.truncate(read);
+        }
+        let available = self.buffer.len() - self.position;
+        if self.limit.is_reached() && available > 0 {
+            return Err(self.limit.error().into());
         }
+        let len = self.limit.allowed(available);
+        Ok(&self.buffer[self.position..self.position + len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = min(amt, self.buffer.len() - self.position);
+        self.position += amt;
+        self.limit.consume(amt);
     }
 }
 
-fn read_header(stream: &mut Stream) -> Result<(Vec<u8>, usize), Error> {
-    let mut buffer = vec![0; 1024];
-    let mut filled = 0usize;
-    let line_ending = loop {
-        if filled == buffer.len() {
-            return Err(Error::Protocol(ProtocolError::HeaderTooLong));
-        }
-        // Compare one byte before, just in case the last one of the previous buffer
-        // was \r and we're reading in \n that follows it just now.
-        let before = filled.saturating_sub(1);
-        match stream.read(&mut buffer[filled..]) {
-            Ok(read) => filled += read,
-            Err(tls::Error::Interrupted) => continue,
+fn read_body(stream: &mut Stream, state: &mut State, buf: &mut [u8]) -> Result<usize, Error> {
+    match state {
+        State::ReadingData => match stream.read(buf) {
+            Ok(read) => Ok(read),
             Err(tls::Error::Terminated) => {
+                *state = State::Complete;
+                Err(Error::Terminated)
+            }
+            Err(tls::Error::Truncated) => {
+                *state = State::Truncated;
+                Err(Error::Truncated)
+            }
+            Err(other) => Err(other.into()),
+        },
+        State::Complete => Err(Error::Terminated),
+        State::Truncated => Err(Error::Truncated),
+    }
+}
+
+fn read_header(stream: &mut Stream, url: &Url) -> Result<(ResponseHeader, Vec<u8>), Error> {
+    let mut buffer = HeaderBuffer::new();
+    loop {
+        let read = match stream.read(buffer.unfilled()?) {
+            Ok(read) => read,
+            Err(tls::Error::Interrupted) => continue,
+            Err(tls::Error::Terminated) | Err(tls::Error::Truncated) => {
                 return Err(Error::Protocol(ProtocolError::UnexpectedEndOfStream))
             }
+            Err(tls::Error::TimedOut) => return Err(Error::FirstByteTimeout),
+            Err(tls::Error::Cancelled) => return Err(Error::Cancelled),
             Err(other) => return Err(other.into()),
+        };
+        if buffer.advance(read) {
+            return Ok(buffer.parse(url)?);
         }
-        if let Some(index) = line_ending(&buffer[before..filled]) {
-            break index;
-        }
-    };
-    buffer.truncate(filled);
-    Ok((buffer, line_ending))
-}
-
-fn line_ending(slice: &[u8]) -> Option<usize> {
-    slice
-        .windows(2)
-        .position(|bytes| bytes == b"\r\n")
-        .map(|index| index)
-}
-
-fn parse_header(header: &[u8]) -> Result<(Status, &str), ProtocolError> {
-    if header.len() < 2 {
-        return Err(ProtocolError::HeaderTooShort);
-    }
-    if header.len() > 2 && header[2] != b' ' {
-        return Err(ProtocolError::HeaderMalformed);
-    }
-    let status = &header[0..2];
-    let meta = if header.len() > 2 { &header[3..] } else { b"" };
-    let status = str::from_utf8(status)
-        .map_err(|_| ProtocolError::HeaderMalformed)?
-        .parse::<u8>()
-        .map_err(|_| ProtocolError::HeaderMalformed)?
-        .try_into()
-        .map_err(|_| ProtocolError::UnknownStatus)?;
-    let meta = str::from_utf8(meta).map_err(|_| ProtocolError::HeaderMalformed)?;
-    Ok((status, meta))
+    }
 }
 
 impl From<tls::Error> for Error {
@@ -168,6 +287,44 @@ impl From<tls::Error> for Error {
             tls::Error::TLS(error) => Self::TLS(error),
             tls::Error::Interrupted => Self::Interrupted,
             tls::Error::Terminated => Self::Terminated,
+            tls::Error::Truncated => Self::Truncated,
+            tls::Error::TimedOut => Self::ReadTimeout,
+            tls::Error::Cancelled => Self::Cancelled,
         }
     }
 }
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::IO(error) => return error,
+            Error::Protocol(_) | Error::TLS(_) => io::ErrorKind::InvalidData,
+            Error::Interrupted => io::ErrorKind::Interrupted,
+            Error::Terminated | Error::Truncated => io::ErrorKind::UnexpectedEof,
+            Error::FirstByteTimeout | Error::ReadTimeout => io::ErrorKind::TimedOut,
+            Error::Cancelled => io::ErrorKind::ConnectionAborted,
+            Error::BodyTooLarge(_) => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_downcast_to_response_errors() {
+        let error: io::Error = Error::Protocol(ProtocolError::HeaderTooLong).into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = error.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(
+            *error,
+            Error::Protocol(ProtocolError::HeaderTooLong)
+        ));
+
+        let original = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        let error: io::Error = Error::IO(original).into();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+    }
+}
@@ -2,24 +2,39 @@ use std::convert::TryFrom;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Status {
-    Input               = 10,
-    SensitiveInput      = 11,
-    Success             = 20,
-    TemporaryRedirect   = 30,
-    PermanentRedirect   = 31,
-    TemporaryFailure    = 40,
-    ServerUnavailable   = 41,
-    CGIError            = 42,
-    ProxyError          = 43,
-    SlowDown            = 44,
-    PermanentFailure    = 50,
-    NotFound            = 51,
-    Gone                = 52,
-    ProxyRequestRefused = 53,
-    BadRequest          = 59,
-    ClientCertRequired  = 60,
-    NotAuthorized       = 61,
-    CertNotValid        = 62,
+    Input,
+    SensitiveInput,
+    Success,
+    TemporaryRedirect,
+    PermanentRedirect,
+    TemporaryFailure,
+    ServerUnavailable,
+    CGIError,
+    ProxyError,
+    SlowDown,
+    PermanentFailure,
+    NotFound,
+    Gone,
+    ProxyRequestRefused,
+    BadRequest,
+    ClientCertRequired,
+    NotAuthorized,
+    CertNotValid,
+    /// Status code not listed in the specification.
+    ///
+    /// Clients are expected to handle it according to its category, given by the first digit.
+    Other { code: u8, category: Category },
+}
+
+/// Status category, given by the first digit of the status code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Input,
+    Success,
+    Redirect,
+    TemporaryFailure,
+    PermanentFailure,
+    ClientCertificate,
 }
 
 impl TryFrom<u8> for Status {
@@ -45,7 +60,118 @@ impl TryFrom<u8> for Status {
             60 => Ok(Self::ClientCertRequired),
             61 => Ok(Self::NotAuthorized),
             62 => Ok(Self::CertNotValid),
-            other => Err(other),
+            code => match Category::try_from(code) {
+                Ok(category) => Ok(Self::Other { code, category }),
+                Err(_) => Err(code),
+            },
+        }
+    }
+}
+
+impl TryFrom<u8> for Category {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        match code / 10 {
+            1 => Ok(Self::Input),
+            2 => Ok(Self::Success),
+            3 => Ok(Self::Redirect),
+            4 => Ok(Self::TemporaryFailure),
+            5 => Ok(Self::PermanentFailure),
+            6 => Ok(Self::ClientCertificate),
+            _ => Err(code),
         }
     }
 }
+
+impl Status {
+    /// Numeric status code.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Input               => 10,
+            Self::SensitiveInput      => 11,
+            Self::Success             => 20,
+            Self::TemporaryRedirect   => 30,
+            Self::PermanentRedirect   => 31,
+            Self::TemporaryFailure    => 40,
+            Self::ServerUnavailable   => 41,
+            Self::CGIError            => 42,
+            Self::ProxyError          => 43,
+            Self::SlowDown            => 44,
+            Self::PermanentFailure    => 50,
+            Self::NotFound            => 51,
+            Self::Gone                => 52,
+            Self::ProxyRequestRefused => 53,
+            Self::BadRequest          => 59,
+            Self::ClientCertRequired  => 60,
+            Self::NotAuthorized       => 61,
+            Self::CertNotValid        => 62,
+            Self::Other { code, .. }  => code,
+        }
+    }
+
+    pub fn category(self) -> Category {
+        match self {
+            Self::Other { category, .. } => category,
+            known => Category::try_from(known.code()).expect("known status codes are valid"),
+        }
+    }
+
+    /// 1x: the server requests input from the user.
+    pub fn is_input(self) -> bool {
+        self.category() == Category::Input
+    }
+
+    /// 2x: the request succeeded, the body follows.
+    pub fn is_success(self) -> bool {
+        self.category() == Category::Success
+    }
+
+    /// 3x: the resource is available elsewhere.
+    pub fn is_redirect(self) -> bool {
+        self.category() == Category::Redirect
+    }
+
+    /// 4x: the request failed, repeating it later may succeed.
+    pub fn is_temporary_failure(self) -> bool {
+        self.category() == Category::TemporaryFailure
+    }
+
+    /// 5x: the request failed, it should not be repeated.
+    pub fn is_permanent_failure(self) -> bool {
+        self.category() == Category::PermanentFailure
+    }
+
+    /// 6x: the request requires a client certificate.
+    pub fn is_client_cert(self) -> bool {
+        self.category() == Category::ClientCertificate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_codes_round_trip() {
+        for code in 0..=u8::MAX {
+            if let Ok(status) = Status::try_from(code) {
+                assert_eq!(status.code(), code);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_codes_fall_back_to_category() {
+        assert_eq!(
+            Status::try_from(21),
+            Ok(Status::Other {
+                code: 21,
+                category: Category::Success
+            })
+        );
+        assert!(Status::try_from(45).unwrap().is_temporary_failure());
+        assert_eq!(Status::try_from(7), Err(7));
+        assert_eq!(Status::try_from(70), Err(70));
+    }
+}
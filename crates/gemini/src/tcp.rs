@@ -1,28 +1,380 @@
+use crate::cancel::CancelToken;
+use crate::host::Host;
+use socket2::{Domain, Protocol, Socket, Type};
+#[cfg(not(unix))]
+use std::thread;
 use std::{
     fmt, io,
     net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
 };
 
-pub fn connect(host: impl ToSocketAddrs + fmt::Debug) -> io::Result<TcpStream> {
-    fn connect(addr: SocketAddr) -> io::Result<Option<TcpStream>> {
-        loop {
-            match TcpStream::connect(addr) {
-                Ok(stream) => return Ok(Some(stream)),
-                Err(err) => match err.kind() {
-                    io::ErrorKind::Interrupted => continue,
-                    io::ErrorKind::ConnectionRefused | io::ErrorKind::TimedOut => return Ok(None),
-                    _ => return Err(err),
-                },
+/// Delay before racing the next address while the previous ones are still connecting.
+///
+/// Reference: RFC 8305, section 5
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How often pending connections check for cancellation.
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Connects to the first reachable address of the host.
+///
+/// Addresses of different families are interleaved and raced as described in RFC 8305.
+/// Each address is given `timeout` to connect, if specified.
+/// If all addresses time out, the error is of `io::ErrorKind::TimedOut` kind.
+///
+/// Connection attempts are abandoned when `cancel` is cancelled, but name resolution is not.
+pub fn connect(
+    host: impl ToSocketAddrs + fmt::Debug,
+    timeout: Option<Duration>,
+    cancel: &CancelToken,
+) -> io::Result<TcpStream> {
+    let addrs = interleave_families(host.to_socket_addrs()?.collect());
+    connect_to_addresses(&addrs, timeout, cancel).map_err(|err| match err.kind() {
+        io::ErrorKind::TimedOut => io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("connection timed out: {:?}", host),
+        ),
+        io::ErrorKind::ConnectionRefused => io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("host unreachable: {:?}", host),
+        ),
+        _ => err,
+    })
+}
+
+/// Connects to `host`, resolving its name if necessary. See `connect` for details.
+pub fn connect_to_host(
+    host: &Host,
+    port: u16,
+    timeout: Option<Duration>,
+    cancel: &CancelToken,
+) -> io::Result<TcpStream> {
+    match host {
+        Host::Domain(domain) => connect((domain.as_str(), port), timeout, cancel),
+        Host::Ip(ip) => connect((*ip, port), timeout, cancel),
+    }
+}
+
+/// Races connections to `addrs`, in the given order.
+///
+/// Attempts are started `CONNECTION_ATTEMPT_DELAY` apart, or as soon as the previous one fails.
+/// The first successful connection is returned. Connections which are still pending are
+/// closed at that point. The same happens to all of them if `cancel` is cancelled.
+pub fn connect_to_addresses(
+    addrs: &[SocketAddr],
+    timeout: Option<Duration>,
+    cancel: &CancelToken,
+) -> io::Result<TcpStream> {
+    let mut pending = addrs.iter();
+    let mut attempts: Vec<Attempt> = Vec::new();
+    let mut next_attempt = Instant::now();
+    let mut timed_out = false;
+    let mut last_error = None;
+    loop {
+        if cancel.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Other, "connection cancelled"));
+        }
+        let now = Instant::now();
+        // Start the next attempt when it's time, or if there is nothing else to wait for.
+        if now >= next_attempt || attempts.is_empty() {
+            if let Some(&addr) = pending.next() {
+                match Attempt::start(addr, timeout) {
+                    Ok(attempt) => {
+                        attempts.push(attempt);
+                        next_attempt = now + CONNECTION_ATTEMPT_DELAY;
+                    }
+                    Err(err) => {
+                        last_error = Some(err);
+                        next_attempt = now;
+                    }
+                }
+                continue;
             }
         }
+        if attempts.is_empty() {
+            break;
+        }
+
+        let mut wait = CANCEL_CHECK_INTERVAL;
+        if pending.len() > 0 {
+            wait = wait.min(next_attempt.saturating_duration_since(now));
+        }
+        for attempt in &attempts {
+            if let Some(deadline) = attempt.deadline {
+                wait = wait.min(deadline.saturating_duration_since(now));
+            }
+        }
+        wait_for_any(&attempts, wait)?;
+
+        let now = Instant::now();
+        let mut index = 0;
+        while index < attempts.len() {
+            match attempts[index].status() {
+                Ok(true) => {
+                    // Dropping the rest closes them.
+                    let socket = attempts.swap_remove(index).socket;
+                    socket.set_nonblocking(false)?;
+                    return Ok(socket.into());
+                }
+                Ok(false) if !attempts[index].is_expired(now) => {
+                    index += 1;
+                    continue;
+                }
+                Ok(false) => {
+                    timed_out = true;
+                    last_error = Some(io::ErrorKind::TimedOut.into());
+                }
+                Err(err) => last_error = Some(err),
+            }
+            attempts.swap_remove(index);
+            // The next address need not wait for the failed one.
+            next_attempt = now;
+        }
+    }
+    if timed_out {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "connection timed out",
+        ));
     }
-    for addr in host.to_socket_addrs()? {
-        if let Some(stream) = connect(addr)? {
-            return Ok(stream);
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::ConnectionRefused, "no addresses to connect")
+    }))
+}
+
+/// Non-blocking connection in progress.
+struct Attempt {
+    socket: Socket,
+    deadline: Option<Instant>,
+}
+
+impl Attempt {
+    fn start(addr: SocketAddr, timeout: Option<Duration>) -> io::Result<Self> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_nonblocking(true)?;
+        match socket.connect(&addr.into()) {
+            Ok(()) => {}
+            Err(err) if is_in_progress(&err) => {}
+            Err(err) => return Err(err),
+        }
+        Ok(Self {
+            socket,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        })
+    }
+
+    /// Whether the connection is established, or why it has failed.
+    fn status(&self) -> io::Result<bool> {
+        if let Some(err) = self.socket.take_error()? {
+            return Err(err);
+        }
+        match self.socket.peer_addr() {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(false),
+            Err(err) => Err(err),
         }
     }
-    Err(io::Error::new(
-        io::ErrorKind::ConnectionRefused,
-        format!("host unreachable: {:?}", host),
-    ))
+
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.deadline, Some(deadline) if now >= deadline)
+    }
+}
+
+#[cfg(unix)]
+fn is_in_progress(error: &io::Error) -> bool {
+    // Interrupted connection continues asynchronously, just as the one in progress.
+    matches!(
+        error.raw_os_error(),
+        Some(libc::EINPROGRESS) | Some(libc::EINTR)
+    )
+}
+
+#[cfg(not(unix))]
+fn is_in_progress(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Waits until some of the attempts complete or `timeout` passes.
+#[cfg(unix)]
+fn wait_for_any(attempts: &[Attempt], timeout: Duration) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fds = attempts
+        .iter()
+        .map(|attempt| libc::pollfd {
+            fd: attempt.socket.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+    // Round up so that deadlines are not missed by a fraction of millisecond.
+    let mut millis = timeout.as_millis();
+    if Duration::from_millis(millis as u64) < timeout {
+        millis += 1;
+    }
+    let timeout = millis.min(i32::MAX as u128) as _;
+    let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    if result < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Sockets are not waited on here, they are checked again after `timeout`,
+/// which is short enough not to delay connections noticeably.
+#[cfg(not(unix))]
+fn wait_for_any(_attempts: &[Attempt], timeout: Duration) -> io::Result<()> {
+    thread::sleep(timeout);
+    Ok(())
+}
+
+/// Reorders addresses so that families alternate, starting with the family of the first one.
+///
+/// Reference: RFC 8305, section 4
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    let mut result = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => result.extend(a.into_iter().chain(b)),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn families_are_interleaved() {
+        let addrs = vec![
+            addr("[2001:db8::1]:1965"),
+            addr("[2001:db8::2]:1965"),
+            addr("[2001:db8::3]:1965"),
+            addr("192.0.2.1:1965"),
+        ];
+        assert_eq!(
+            interleave_families(addrs),
+            vec![
+                addr("[2001:db8::1]:1965"),
+                addr("192.0.2.1:1965"),
+                addr("[2001:db8::2]:1965"),
+                addr("[2001:db8::3]:1965"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unreachable_address_does_not_stall() {
+        let ipv4 = TcpListener::bind("127.0.0.1:0").unwrap();
+        // IPv6 may be disabled in the test environment.
+        let ipv6 = TcpListener::bind("[::1]:0").ok();
+
+        // TEST-NET-1 is not routable, connections to it either fail or hang.
+        let mut addrs = vec![addr("192.0.2.1:1965")];
+        addrs.extend(ipv6.iter().map(|listener| listener.local_addr().unwrap()));
+        addrs.push(ipv4.local_addr().unwrap());
+
+        let start = Instant::now();
+        let stream =
+            connect_to_addresses(&addrs, Some(Duration::from_secs(10)), &CancelToken::new())
+                .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(addrs[1..].contains(&stream.peer_addr().unwrap()));
+    }
+
+    /// Number of sockets connecting to `addr`, which have sent SYN but got nothing back.
+    #[cfg(target_os = "linux")]
+    fn connecting_to(addr: SocketAddr) -> usize {
+        let port = format!(":{:04X}", addr.port());
+        std::fs::read_to_string("/proc/net/tcp")
+            .unwrap()
+            .lines()
+            .skip(1)
+            .filter(|line| {
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                // Remote address, and SYN_SENT state.
+                fields[2].ends_with(&port) && fields[3] == "02"
+            })
+            .count()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn losing_attempts_are_closed() {
+        // Listener with full backlog ignores new connections, leaving them to retry SYN.
+        let stalled = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        stalled.bind(&addr("127.0.0.1:0").into()).unwrap();
+        stalled.listen(0).unwrap();
+        let stalled = stalled.local_addr().unwrap().as_socket().unwrap();
+        let _backlog = (0..2)
+            .map(|_| TcpStream::connect_timeout(&stalled, Duration::from_millis(100)))
+            .collect::<Vec<_>>();
+        let probe = Attempt::start(stalled, None).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!probe.status().unwrap());
+        assert_eq!(connecting_to(stalled), 1);
+        drop(probe);
+        assert_eq!(connecting_to(stalled), 0);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [stalled, listener.local_addr().unwrap()];
+        let stream = connect_to_addresses(&addrs, None, &CancelToken::new()).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addrs[1]);
+        assert_eq!(connecting_to(stalled), 0);
+    }
+
+    #[test]
+    fn refused_address_is_skipped() {
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap();
+        let refused_addr = refused.local_addr().unwrap();
+        drop(refused);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [refused_addr, listener.local_addr().unwrap()];
+
+        let start = Instant::now();
+        let stream = connect_to_addresses(&addrs, None, &CancelToken::new()).unwrap();
+        assert!(start.elapsed() < CONNECTION_ATTEMPT_DELAY);
+        assert_eq!(stream.peer_addr().unwrap(), addrs[1]);
+    }
+
+    #[test]
+    fn all_addresses_fail() {
+        let refused = TcpListener::bind("127.0.0.1:0").unwrap();
+        let refused_addr = refused.local_addr().unwrap();
+        drop(refused);
+        let error = connect_to_addresses(&[refused_addr], None, &CancelToken::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        let error = connect_to_addresses(&[], None, &CancelToken::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn cancelled_before_connecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let result = connect_to_addresses(&[listener.local_addr().unwrap()], None, &cancel);
+        assert!(result.is_err());
+    }
 }
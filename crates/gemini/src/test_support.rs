@@ -0,0 +1,27 @@
+//! Fixtures shared by unit tests.
+
+use crate::verify::Endpoint;
+use rustls::Certificate;
+use url::Url;
+
+/// Self-signed certificate issued with `params`, and its DER-encoded private key.
+pub(crate) fn self_signed(params: rcgen::CertificateParams) -> (Certificate, Vec<u8>) {
+    let certificate = rcgen::Certificate::from_params(params).unwrap();
+    (
+        Certificate(certificate.serialize_der().unwrap()),
+        certificate.serialize_private_key_der(),
+    )
+}
+
+/// Certificate for example.com, with a new key unless `key` is given.
+pub(crate) fn certificate(key: Option<&[u8]>) -> (Certificate, Vec<u8>) {
+    let mut params = rcgen::CertificateParams::new(vec!["example.com".to_owned()]);
+    if let Some(key) = key {
+        params.key_pair = Some(rcgen::KeyPair::from_der(key).unwrap());
+    }
+    self_signed(params)
+}
+
+pub(crate) fn endpoint(url: &str) -> Endpoint {
+    Endpoint::from_url(&Url::parse(url).unwrap()).unwrap()
+}
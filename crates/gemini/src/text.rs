@@ -0,0 +1,186 @@
+use crate::response::{Error, Response};
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_8};
+
+/// Decodes text body of a response into strings, according to its charset.
+///
+/// Multi-byte sequences split between reads are handled transparently.
+/// Malformed input is replaced with U+FFFD, unknown charsets are decoded as UTF-8.
+/// Either case is noted in the report instead of failing.
+pub struct TextDecoder {
+    response: Response,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+    report: DecodingReport,
+    finished: bool,
+}
+
+/// Describes how the body has been decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodingReport {
+    /// Name of the encoding used for decoding.
+    pub encoding: &'static str,
+    /// Charset label of the response, if it has not been recognized.
+    pub unknown_charset: Option<String>,
+    /// Whether malformed sequences have been replaced with U+FFFD.
+    pub had_replacements: bool,
+}
+
+const CHUNK_SIZE: usize = 4096;
+
+impl TextDecoder {
+    /// Decodes response body using the charset of its media type.
+    pub fn new(response: Response) -> Self {
+        let charset = response
+            .media_type()
+            .map(|media_type| media_type.charset().to_owned());
+        match charset {
+            Some(charset) => Self::with_charset(response, &charset),
+            None => Self::with_encoding(response, UTF_8, None),
+        }
+    }
+
+    /// Decodes response body using the given charset, regardless of its media type.
+    pub fn with_charset(response: Response, charset: &str) -> Self {
+        match Encoding::for_label(charset.trim().as_bytes()) {
+            Some(encoding) => Self::with_encoding(response, encoding, None),
+            None => Self::with_encoding(response, UTF_8, Some(charset.to_owned())),
+        }
+    }
+
+    fn with_encoding(
+        response: Response,
+        encoding: &'static Encoding,
+        unknown_charset: Option<String>,
+    ) -> Self {
+        Self {
+            response,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            buffer: vec![0; CHUNK_SIZE],
+            report: DecodingReport {
+                encoding: encoding.name(),
+                unknown_charset,
+                had_replacements: false,
+            },
+            finished: false,
+        }
+    }
+
+    pub fn response(&self) -> &Response {
+        &self.response
+    }
+
+    pub fn into_response(self) -> Response {
+        self.response
+    }
+
+    /// Report on the decoding so far.
+    pub fn report(&self) -> &DecodingReport {
+        &self.report
+    }
+
+    /// Reads and decodes next chunk of the body.
+    ///
+    /// Returns `None` when the body has been read completely.
+    pub fn read_chunk(&mut self) -> Result<Option<String>, Error> {
+        while !self.finished {
+            let read = match self.response.read(&mut self.buffer) {
+                Ok(0) | Err(Error::Terminated) => 0,
+                Ok(read) => read,
+                Err(Error::Interrupted) => continue,
+                Err(err) => return Err(err),
+            };
+            let last = read == 0;
+            let mut chunk = String::new();
+            decode(
+                &mut self.decoder,
+                &self.buffer[..read],
+                &mut chunk,
+                last,
+                &mut self.report,
+            );
+            self.finished = last;
+            if !chunk.is_empty() {
+                return Ok(Some(chunk));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads and decodes the rest of the body.
+    pub fn read_to_string(&mut self) -> Result<String, Error> {
+        let mut string = String::new();
+        while let Some(chunk) = self.read_chunk()? {
+            string.push_str(&chunk);
+        }
+        Ok(string)
+    }
+}
+
+fn decode(
+    decoder: &mut Decoder,
+    mut input: &[u8],
+    output: &mut String,
+    last: bool,
+    report: &mut DecodingReport,
+) {
+    loop {
+        if let Some(length) = decoder.max_utf8_buffer_length(input.len()) {
+            output.reserve(length);
+        }
+        let (result, read, had_replacements) = decoder.decode_to_string(input, output, last);
+        report.had_replacements |= had_replacements;
+        input = &input[read..];
+        match result {
+            CoderResult::InputEmpty => break,
+            CoderResult::OutputFull => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_chunks(charset: &str, chunks: &[&[u8]]) -> (String, DecodingReport) {
+        let encoding = Encoding::for_label(charset.as_bytes()).unwrap_or(UTF_8);
+        let mut decoder = encoding.new_decoder_with_bom_removal();
+        let mut report = DecodingReport {
+            encoding: encoding.name(),
+            unknown_charset: None,
+            had_replacements: false,
+        };
+        let mut output = String::new();
+        for chunk in chunks {
+            decode(&mut decoder, chunk, &mut output, false, &mut report);
+        }
+        decode(&mut decoder, &[], &mut output, true, &mut report);
+        (output, report)
+    }
+
+    #[test]
+    fn split_multibyte_sequences() {
+        let (text, report) = decode_chunks("utf-8", &[b"\xD0\x9F\xD1", b"\x80\xD0\xB8"]);
+        assert_eq!(text, "При");
+        assert!(!report.had_replacements);
+
+        let (text, _) = decode_chunks("shift_jis", &[b"\x82", b"\xA0\x82\xA2"]);
+        assert_eq!(text, "あい");
+    }
+
+    #[test]
+    fn single_byte_encodings() {
+        let (text, _) = decode_chunks("koi8-r", &[b"\xF0\xD2\xC9"]);
+        assert_eq!(text, "При");
+
+        let (text, report) = decode_chunks("latin1", &[b"caf\xE9"]);
+        assert_eq!(text, "café");
+        assert_eq!(report.encoding, "windows-1252");
+    }
+
+    #[test]
+    fn malformed_input_is_replaced() {
+        let (text, report) = decode_chunks("utf-8", &[b"ok\xFF", b"\xD0"]);
+        assert_eq!(text, "ok\u{FFFD}\u{FFFD}");
+        assert!(report.had_replacements);
+    }
+}
@@ -1,14 +1,35 @@
+use crate::cancel::{CancelGuard, CancelToken};
+use crate::host::Host;
 use crate::tcp;
 use rustls::{ClientConfig, ClientSession, Session, TLSError};
+use std::fmt;
 use std::io;
 use std::io::{Read, Write};
-use std::{net::TcpStream, sync::Arc};
-use webpki::DNSNameRef;
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
 pub struct Stream {
     stream: TcpStream,
     session: ClientSession,
+    eof: bool,
+    deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    cancel: CancelToken,
+    _cancel_guard: CancelGuard,
+}
+
+// Cancellation guard has nothing to show.
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field("stream", &self.stream)
+            .field("session", &self.session)
+            .field("eof", &self.eof)
+            .field("deadline", &self.deadline)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -17,6 +38,12 @@ pub enum Error {
     Interrupted,
     #[error("connection terminated")]
     Terminated,
+    #[error("connection closed without close_notify")]
+    Truncated,
+    #[error("operation timed out")]
+    TimedOut,
+    #[error("operation cancelled")]
+    Cancelled,
     #[error(transparent)]
     IO(io::Error),
     #[error(transparent)]
@@ -24,19 +51,86 @@ pub enum Error {
 }
 
 impl Stream {
-    pub fn new(host: &str, port: u16, config: &Arc<ClientConfig>) -> io::Result<Self> {
-        let stream = tcp::connect((host, port))?;
-        let hostname = DNSNameRef::try_from_ascii_str(host).expect("hostname must be valid");
+    pub fn new(
+        host: &Host,
+        port: u16,
+        config: &Arc<ClientConfig>,
+        connect_timeout: Option<Duration>,
+        cancel: &CancelToken,
+    ) -> io::Result<Self> {
+        let hostname = host
+            .session_name()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid host name"))?;
+        let stream = tcp::connect_to_host(host, port, connect_timeout, cancel)?;
+        // Shutting down the socket wakes up whoever is blocked on it.
+        let socket = stream.try_clone()?;
+        let cancel_guard = cancel.on_cancel(move || {
+            let _ = socket.shutdown(Shutdown::Both);
+        });
         let session = ClientSession::new(config, hostname);
-        Ok(Self { stream, session })
+        Ok(Self {
+            stream,
+            session,
+            eof: false,
+            deadline: None,
+            idle_timeout: None,
+            cancel: cancel.clone(),
+            _cancel_guard: cancel_guard,
+        })
+    }
+
+    /// Sets the point in time after which all operations fail with `Error::TimedOut`.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Sets the time any single read or write may block for before failing with `Error::TimedOut`.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    fn update_socket_timeout(&mut self) -> Result<(), Error> {
+        let remaining = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if remaining > Duration::from_millis(0) => Some(remaining),
+                _ => return Err(Error::TimedOut),
+            },
+            None => None,
+        };
+        let timeout = match (remaining, self.idle_timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.set_write_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Reports any error as `Error::Cancelled` once the stream is cancelled.
+    fn check_cancelled<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        match result {
+            Err(_) if self.cancel.is_cancelled() => Err(Error::Cancelled),
+            result => result,
+        }
     }
 
     fn complete_io(&mut self) -> Result<(), Error> {
+        if self.cancel.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let result = self.complete_io_inner();
+        self.check_cancelled(result)
+    }
+
+    fn complete_io_inner(&mut self) -> Result<(), Error> {
+        self.update_socket_timeout()?;
         if self.session.wants_write() {
             self.session.write_tls(&mut self.stream)?;
         }
         if self.session.wants_read() {
-            self.session.read_tls(&mut self.stream)?;
+            if self.session.read_tls(&mut self.stream)? == 0 {
+                self.eof = true;
+            }
             self.session.process_new_packets()?;
         }
         Ok(())
@@ -45,13 +139,39 @@ impl Stream {
     pub fn establish_connection(&mut self) -> Result<(), Error> {
         while self.session.is_handshaking() {
             self.complete_io()?;
+            if self.eof && self.session.is_handshaking() {
+                return self.check_cancelled(Err(Error::IO(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed during handshake",
+                ))));
+            }
         }
         Ok(())
     }
 
+    /// Reads decrypted data from the stream.
+    ///
+    /// Returns `Error::Terminated` once the server has sent close_notify and all data is read.
+    /// If the connection is closed without close_notify, returns `Error::Truncated` instead.
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
-        self.complete_io()?;
-        Ok(self.session.read(buf)?)
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            self.complete_io()?;
+            match self.session.read(buf) {
+                // Session has no data yet. Maybe the record did not contain any,
+                // or maybe the connection is gone for good.
+                Ok(0) if self.eof => return self.check_cancelled(Err(Error::Truncated)),
+                Ok(0) => continue,
+                Ok(read) => return Ok(read),
+                // That's how the session reports close_notify.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => {
+                    return Err(Error::Terminated)
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
     }
 
     pub fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
@@ -70,9 +190,9 @@ impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         match error.kind() {
             io::ErrorKind::Interrupted => Self::Interrupted,
-            io::ErrorKind::ConnectionAborted => Self::Terminated,
+            // Sockets report timeouts differently on different platforms.
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Self::TimedOut,
             _ => Self::IO(error),
         }
     }
 }
-
@@ -1,14 +1,63 @@
+use crate::fingerprints::Fingerprint;
+use crate::host::Host;
+use crate::pins::StaticPins;
+use crate::policy::{Verdict, VerificationPolicy};
+use crate::request::{UrlError, DEFAULT_GEMINI_PORT};
 use crate::x509;
-use rustls::{Certificate, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
-use webpki::DNSNameRef;
+use rustls::{Certificate, RootCertStore, ServerCertVerified, TLSError, WebPKIVerifier};
+use std::collections::HashMap;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+use url::Url;
 use x509_parser::certificate::X509Certificate;
 
-/// `ServerCertVerifier` implementing TOFU-style verification.
+/// Verifies certificates presented by servers.
+///
+/// Implemented by all `VerificationPolicy` types.
+pub trait ServerVerifier: Send + Sync {
+    fn verify_server_cert(
+        &self,
+        presented_certs: &[Certificate],
+        endpoint: &Endpoint,
+    ) -> Result<ServerCertVerified, TLSError>;
+}
+
+/// Server which presents a certificate, and the request it is presented for.
+///
+/// Trust is established separately for each port of a host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: Host,
+    pub port: u16,
+    /// URL of the request which connects to the server.
+    pub url: Url,
+}
+
+impl Endpoint {
+    pub fn from_url(url: &Url) -> Result<Self, UrlError> {
+        Ok(Self {
+            host: Host::from_url(url)?,
+            port: url.port().unwrap_or(DEFAULT_GEMINI_PORT),
+            url: url.clone(),
+        })
+    }
+}
+
+/// Verification policy implementing TOFU-style verification.
+///
+/// Concurrent verifications of the same certificate for the same endpoint ask the delegate
+/// only once, the rest wait for its decision.
 ///
 /// Reference: gemini://drewdevault.com/2020/09/21/Gemini-TOFU.gmi
 pub struct CertificateVerifier<D, C> {
     delegate: D,
     trust_cache: C,
+    rotation: RotationPolicy,
+    authorities: Option<RootCertStore>,
+    pins: StaticPins,
+    prompts: Prompts,
 }
 
 impl<D, C> CertificateVerifier<D, C>
@@ -20,104 +69,919 @@ where
         Self {
             delegate,
             trust_cache,
+            rotation: RotationPolicy::default(),
+            authorities: None,
+            pins: StaticPins::new(),
+            prompts: Prompts::default(),
         }
     }
+
+    /// Sets how changes of trusted certificates are handled. By default, any change is reported
+    /// as `VerificationIssue::FingerprintMismatch`.
+    pub fn rotation_policy(mut self, policy: RotationPolicy) -> Self {
+        self.rotation = policy;
+        self
+    }
+
+    /// Verifies presented certificate chains against given root certificates.
+    ///
+    /// Chains which are valid for the host are reported in `CertificateDetails::trusted_chain`
+    /// and may justify certificate rotation. Otherwise, they are treated as usual.
+    /// By default, only the first certificate is looked at.
+    pub fn certificate_authorities(mut self, roots: RootCertStore) -> Self {
+        self.authorities = Some(roots);
+        self
+    }
+
+    /// Checks pinned hosts against their pins before anything else.
+    ///
+    /// Certificates of pinned hosts which do not match the pins are rejected without asking
+    /// the delegate, even if the trust cache has trusted them.
+    pub fn static_pins(mut self, pins: StaticPins) -> Self {
+        self.pins = pins;
+        self
+    }
+}
+
+/// Whether the presented chain is issued for the host by a certificate authority from `roots`.
+pub(crate) fn has_trusted_chain(
+    roots: &RootCertStore,
+    presented_certs: &[Certificate],
+    host: &Host,
+) -> bool {
+    // webpki does not support IP addresses in certificates.
+    let name = match host {
+        Host::Domain(domain) => domain,
+        Host::Ip(_) => return false,
+    };
+    let name = match webpki::DNSNameRef::try_from_ascii_str(name) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    rustls::ServerCertVerifier::verify_server_cert(
+        &WebPKIVerifier::new(),
+        roots,
+        presented_certs,
+        name,
+        &[],
+    )
+    .is_ok()
+}
+
+/// Root certificates of Mozilla, as bundled with webpki-roots.
+#[cfg(feature = "webpki-roots")]
+pub fn webpki_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    roots
+}
+
+/// Reads root certificates from a PEM bundle, skipping ones which are not supported.
+pub fn load_roots(path: impl AsRef<Path>) -> io::Result<RootCertStore> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut roots = RootCertStore::empty();
+    match roots.add_pem_file(&mut reader) {
+        Ok((added, _)) if added > 0 => Ok(roots),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no root certificates in bundle",
+        )),
+    }
 }
 
-impl<D, C> ServerCertVerifier for CertificateVerifier<D, C>
+impl<D, C> VerificationPolicy for CertificateVerifier<D, C>
 where
     D: VerificationDelegate,
     C: CertificateTrustCache,
 {
-    fn verify_server_cert(
+    fn verify(&self, presented_certs: &[Certificate], endpoint: &Endpoint) -> Verdict {
+        self.verify_certificate(presented_certs, endpoint).into()
+    }
+}
+
+impl<D, C> CertificateVerifier<D, C>
+where
+    D: VerificationDelegate,
+    C: CertificateTrustCache,
+{
+    fn verify_certificate(
         &self,
-        _roots: &RootCertStore,
         presented_certs: &[Certificate],
-        dns_name: DNSNameRef<'_>,
-        _ocsp_response: &[u8],
+        endpoint: &Endpoint,
     ) -> Result<ServerCertVerified, TLSError> {
+        match self.pins.verify(presented_certs, endpoint) {
+            Verdict::Accept => return Ok(ServerCertVerified::assertion()),
+            Verdict::Reject(err) => return Err(err),
+            Verdict::Abstain => {}
+        }
+
         let certificate = presented_certs
             .first()
             .ok_or(TLSError::NoCertificatesPresented)?;
+        let fingerprint = Fingerprint::new(certificate);
 
-        // TODO(2021-02-14): can we use a lighter parser here?
-        // First, parse the alleged certificate data. If it does not parse, that's not valid.
+        // Known certificates are recognized by their fingerprint. If the cache remembers enough
+        // to tell that the certificate is still valid for requested host, that's it.
+        if let Some(pinned) = self
+            .trust_cache
+            .get_trusted_certificate(&fingerprint, endpoint)
+        {
+            if pinned.is_valid_for(&endpoint.host) {
+                return Ok(ServerCertVerified::assertion());
+            }
+        }
+
+        // Otherwise, parse the alleged certificate data. If it does not parse, that's not valid.
         let (_, certificate) = x509_parser::parse_x509_certificate(certificate.as_ref())
             .map_err(|_| TLSError::WebPKIError(webpki::Error::BadDER))?;
+        let mut details = CertificateDetails::new(&certificate, &fingerprint);
+        details.trusted_chain = match &self.authorities {
+            Some(roots) => has_trusted_chain(roots, presented_certs, &endpoint.host),
+            None => false,
+        };
 
-        // Then check whether the certificate is valid for requested domain.
-        let validity = x509::check_certificate_for_domain(&certificate, dns_name);
+        // Then check whether the certificate is valid for requested host.
+        let validity = x509::check_certificate_for_host(&certificate, &endpoint.host);
         let issue = match validity {
             // If the certificate is valid, check the cache if it's already trusted.
             // If it's not trusted, postpone the decision for a while.
-            Ok(()) => match self
-                .trust_cache
-                .get_certificate_trust(&certificate, dns_name)
-            {
-                Response::UnknownCertificate => VerificationIssue::UnknownCertificate,
-                Response::FingerprintMismatch => VerificationIssue::FingerprintMismatch,
-                Response::TrustedCertificate => {
-                    return Ok(ServerCertVerified::assertion());
+            Ok(()) => {
+                match self
+                    .trust_cache
+                    .get_certificate_trust(&certificate, &fingerprint, endpoint)
+                {
+                    Response::UnknownCertificate => VerificationIssue::UnknownCertificate,
+                    Response::FingerprintMismatch(pinned) => {
+                        match self.rotation.applicable_rule(&pinned, &details) {
+                            Some((rule, RotationAction::Accept)) => {
+                                self.trust_cache.trust_certificate_always(
+                                    &certificate,
+                                    &fingerprint,
+                                    endpoint,
+                                );
+                                self.delegate
+                                    .certificate_rotated(&certificate, endpoint, rule);
+                                return Ok(ServerCertVerified::assertion());
+                            }
+                            Some((rule, RotationAction::Prompt)) => {
+                                VerificationIssue::CertificateRotated(rule, pinned)
+                            }
+                            Some((_, RotationAction::Strict)) | None => {
+                                VerificationIssue::FingerprintMismatch(pinned)
+                            }
+                        }
+                    }
+                    Response::TrustedCertificate => {
+                        return Ok(ServerCertVerified::assertion());
+                    }
                 }
-            },
+            }
             Err(err) => VerificationIssue::InvalidCertificate(err),
         };
 
         // If we see any issues with certificate, ask the delegate wat do.
-        match self
-            .delegate
-            .decide_certificate_trust(&certificate, dns_name, issue)
-        {
+        // Unless someone is already asking about the same certificate, then wait for them.
+        let prompt = (endpoint.host.clone(), endpoint.port, fingerprint.clone());
+        let decision = self.prompts.coalesce(prompt, || {
+            let decision =
+                self.delegate
+                    .decide_certificate_trust(&certificate, &details, endpoint, issue);
+            // Remember the decision before the waiters are released.
+            match decision {
+                TrustDecision::Abort => {}
+                TrustDecision::TrustTemporary => {
+                    self.trust_cache
+                        .trust_certificate_once(&certificate, &fingerprint, endpoint)
+                }
+                TrustDecision::TrustAlways => {
+                    self.trust_cache
+                        .trust_certificate_always(&certificate, &fingerprint, endpoint)
+                }
+            }
+            decision
+        });
+        match decision {
             TrustDecision::Abort => Err(TLSError::General("certificate rejected".to_owned())),
-            TrustDecision::TrustTemporary => {
-                self.trust_cache
-                    .trust_certificate_once(&certificate, dns_name);
+            TrustDecision::TrustTemporary | TrustDecision::TrustAlways => {
                 Ok(ServerCertVerified::assertion())
             }
-            TrustDecision::TrustAlways => {
-                self.trust_cache
-                    .trust_certificate_always(&certificate, dns_name);
-                Ok(ServerCertVerified::assertion())
+        }
+    }
+}
+
+type PromptKey = (Host, u16, Fingerprint);
+
+/// Decisions which are being made by the delegate.
+#[derive(Default)]
+struct Prompts {
+    pending: Mutex<HashMap<PromptKey, Arc<Slot>>>,
+}
+
+impl Prompts {
+    /// Makes the decision with `decide`, or waits for a concurrent call with the same `key`.
+    fn coalesce(&self, key: PromptKey, decide: impl FnOnce() -> TrustDecision) -> TrustDecision {
+        let slot = {
+            let mut pending = self.pending.lock().unwrap();
+            if let Some(slot) = pending.get(&key) {
+                let slot = slot.clone();
+                drop(pending);
+                return slot.wait();
+            }
+            let slot = Arc::new(Slot::default());
+            pending.insert(key.clone(), slot.clone());
+            slot
+        };
+        let guard = PromptGuard {
+            prompts: self,
+            key,
+            slot,
+        };
+        let decision = decide();
+        guard.slot.set(decision);
+        decision
+    }
+}
+
+/// Releases the waiters, aborting them if the delegate panics.
+struct PromptGuard<'a> {
+    prompts: &'a Prompts,
+    key: PromptKey,
+    slot: Arc<Slot>,
+}
+
+impl Drop for PromptGuard<'_> {
+    fn drop(&mut self) {
+        self.prompts.pending.lock().unwrap().remove(&self.key);
+        self.slot.set(TrustDecision::Abort);
+    }
+}
+
+/// Trust decision which is yet to be made.
+#[derive(Default)]
+struct Slot {
+    decision: Mutex<Option<TrustDecision>>,
+    decided: Condvar,
+}
+
+impl Slot {
+    /// Makes the decision, unless it's already made.
+    fn set(&self, decision: TrustDecision) {
+        let mut slot = self.decision.lock().unwrap();
+        if slot.is_none() {
+            *slot = Some(decision);
+            self.decided.notify_all();
+        }
+    }
+
+    fn wait(&self) -> TrustDecision {
+        let mut slot = self.decision.lock().unwrap();
+        loop {
+            match *slot {
+                Some(decision) => return decision,
+                None => slot = self.decided.wait(slot).unwrap(),
             }
         }
     }
+
+    fn wait_timeout(&self, timeout: Duration) -> Option<TrustDecision> {
+        let slot = self.decision.lock().unwrap();
+        let (slot, _) = self
+            .decided
+            .wait_timeout_while(slot, timeout, |slot| slot.is_none())
+            .unwrap();
+        *slot
+    }
+}
+
+/// Trust decision which is made elsewhere, for example by the user answering a dialog.
+///
+/// Delegates may hand out the `TrustPrompt` and park until it is answered:
+///
+/// ```no_run
+/// # use cartouche_gemini::verify::{PendingDecision, TrustDecision, TrustPrompt};
+/// # use std::sync::mpsc::Sender;
+/// fn decide(ui: &Sender<TrustPrompt>) -> TrustDecision {
+///     let (decision, prompt) = PendingDecision::new();
+///     match ui.send(prompt) {
+///         Ok(()) => decision.wait(),
+///         Err(_) => TrustDecision::Abort,
+///     }
+/// }
+/// ```
+pub struct PendingDecision {
+    slot: Arc<Slot>,
+}
+
+impl PendingDecision {
+    pub fn new() -> (Self, TrustPrompt) {
+        let slot = Arc::new(Slot::default());
+        (Self { slot: slot.clone() }, TrustPrompt { slot })
+    }
+
+    /// Blocks until the prompt is answered, or dropped which aborts.
+    pub fn wait(self) -> TrustDecision {
+        self.slot.wait()
+    }
+
+    /// Blocks until the prompt is answered or dropped, or `timeout` passes.
+    pub fn wait_timeout(self, timeout: Duration) -> Option<TrustDecision> {
+        self.slot.wait_timeout(timeout)
+    }
+}
+
+/// Answers a `PendingDecision`. Dropping the prompt without an answer aborts.
+pub struct TrustPrompt {
+    slot: Arc<Slot>,
+}
+
+impl TrustPrompt {
+    pub fn answer(self, decision: TrustDecision) {
+        self.slot.set(decision);
+    }
+}
+
+impl Drop for TrustPrompt {
+    fn drop(&mut self) {
+        self.slot.set(TrustDecision::Abort);
+    }
 }
 
 pub trait VerificationDelegate: Send + Sync {
+    /// Decides whether to trust `certificate` despite the `issue`.
+    ///
+    /// `details` summarize the certificate for the user.
+    ///
+    /// The delegate may block until the user decides, see `PendingDecision`.
+    /// Meanwhile, other requests presenting the same certificate for the endpoint wait
+    /// for the same decision instead of asking again.
     fn decide_certificate_trust(
         &self,
         certificate: &X509Certificate<'_>,
-        dns_name: DNSNameRef<'_>,
+        details: &CertificateDetails,
+        endpoint: &Endpoint,
         issue: VerificationIssue,
     ) -> TrustDecision;
+
+    /// Called when `certificate` replaces the trusted one without asking,
+    /// because `rule` of the rotation policy allows that.
+    fn certificate_rotated(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _endpoint: &Endpoint,
+        _rule: RotationRule,
+    ) {
+    }
 }
 
+#[derive(Debug, PartialEq)]
 pub enum VerificationIssue {
     UnknownCertificate,
+    /// Certificate is expired, not valid yet, or not issued for the host.
+    /// In the latter case, `CertificateDetails::names` lists the names it is issued for.
     InvalidCertificate(webpki::Error),
-    FingerprintMismatch,
+    /// Certificate differs from the trusted one, described here.
+    FingerprintMismatch(PinnedCertificate),
+    /// Certificate differs from the trusted one, but `rule` suggests a legitimate change.
+    CertificateRotated(RotationRule, PinnedCertificate),
 }
 
+/// Summary of a certificate presented by a server, for explaining trust decisions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CertificateDetails {
+    pub fingerprint: Fingerprint,
+    /// Fingerprint of DER-encoded SubjectPublicKeyInfo.
+    pub public_key: Option<Fingerprint>,
+    /// Distinguished name of the subject.
+    pub subject: String,
+    /// Distinguished name of the issuer.
+    pub issuer: String,
+    /// Whether the certificate is signed by its own key, not by a certificate authority.
+    pub self_signed: bool,
+    /// Whether the presented chain is valid for the host up to a trusted root certificate.
+    /// Always false unless the verifier is given `certificate_authorities`.
+    pub trusted_chain: bool,
+    /// Names the certificate is issued for, see `x509::certificate_names`.
+    pub names: Vec<String>,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+impl CertificateDetails {
+    /// Describes `certificate` with given `fingerprint`.
+    pub fn new(certificate: &X509Certificate<'_>, fingerprint: &Fingerprint) -> Self {
+        Self {
+            fingerprint: fingerprint.clone(),
+            public_key: x509::subject_public_key_info(certificate).map(Fingerprint::new),
+            subject: certificate.subject().to_string(),
+            issuer: certificate.issuer().to_string(),
+            self_signed: x509::is_self_signed(certificate),
+            trusted_chain: false,
+            names: x509::certificate_names(certificate),
+            not_before: x509::system_time(certificate.validity().not_before),
+            not_after: x509::system_time(certificate.validity().not_after),
+        }
+    }
+}
+
+/// Rules of `RotationPolicy` which may explain why a trusted certificate has changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationRule {
+    /// Previously trusted certificate has expired.
+    PinnedExpired,
+    /// New certificate has the same public key as the previously trusted one.
+    SamePublicKey,
+    /// New certificate is issued for the host by a trusted certificate authority.
+    TrustedChain,
+}
+
+/// What to do with a new certificate when a rotation rule applies to it.
+///
+/// Rules apply only to certificates which are valid for the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RotationAction {
+    /// Report the change as `VerificationIssue::FingerprintMismatch`.
+    Strict,
+    /// Ask the delegate, reporting `VerificationIssue::CertificateRotated`.
+    Prompt,
+    /// Trust the new certificate always, notifying the delegate.
+    Accept,
+}
+
+/// How changes of trusted certificates are handled.
+///
+/// Reference: gemini://drewdevault.com/2020/09/21/Gemini-TOFU.gmi
+/// suggests accepting new certificates once the trusted one expires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RotationPolicy {
+    pub pinned_expired: RotationAction,
+    pub same_public_key: RotationAction,
+    pub trusted_chain: RotationAction,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            pinned_expired: RotationAction::Strict,
+            same_public_key: RotationAction::Strict,
+            trusted_chain: RotationAction::Strict,
+        }
+    }
+}
+
+impl RotationPolicy {
+    /// The most lenient rule which applies to replacing `pinned` certificate with the new one.
+    fn applicable_rule(
+        &self,
+        pinned: &PinnedCertificate,
+        details: &CertificateDetails,
+    ) -> Option<(RotationRule, RotationAction)> {
+        let same_public_key = match (&pinned.public_key, &details.public_key) {
+            (Some(pinned), Some(public_key)) => pinned == public_key,
+            _ => false,
+        };
+        let rules = [
+            (
+                same_public_key,
+                RotationRule::SamePublicKey,
+                self.same_public_key,
+            ),
+            (
+                pinned.not_after < SystemTime::now(),
+                RotationRule::PinnedExpired,
+                self.pinned_expired,
+            ),
+            (
+                details.trusted_chain,
+                RotationRule::TrustedChain,
+                self.trusted_chain,
+            ),
+        ];
+        rules
+            .iter()
+            .filter(|(applies, _, _)| *applies)
+            .map(|(_, rule, action)| (*rule, *action))
+            .max_by_key(|(_, action)| *action)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrustDecision {
     Abort,
     TrustTemporary,
     TrustAlways,
 }
 
+/// Remembers certificates trusted for hosts and their ports.
+///
+/// `fingerprint` is computed over the DER form of `certificate`.
 pub trait CertificateTrustCache: Send + Sync {
+    /// Describes the certificate with `fingerprint` if it is trusted for the endpoint,
+    /// before the certificate is parsed.
+    ///
+    /// Certificates described completely enough are accepted without parsing if they are
+    /// still valid, others go through `get_certificate_trust` as usual.
+    fn get_trusted_certificate(
+        &self,
+        _fingerprint: &Fingerprint,
+        _endpoint: &Endpoint,
+    ) -> Option<PinnedCertificate> {
+        None
+    }
+
     fn get_certificate_trust(
         &self,
         certificate: &X509Certificate<'_>,
-        dns_name: DNSNameRef<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
     ) -> Response;
 
-    fn trust_certificate_once(&self, certificate: &X509Certificate<'_>, dns_name: DNSNameRef<'_>);
+    fn trust_certificate_once(
+        &self,
+        certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    );
 
-    fn trust_certificate_always(&self, certificate: &X509Certificate<'_>, dns_name: DNSNameRef<'_>);
+    fn trust_certificate_always(
+        &self,
+        certificate: &X509Certificate<'_>,
+        fingerprint: &Fingerprint,
+        endpoint: &Endpoint,
+    );
 }
 
 pub enum Response {
     UnknownCertificate,
     TrustedCertificate,
-    FingerprintMismatch,
+    /// Another certificate is trusted for the host.
+    FingerprintMismatch(PinnedCertificate),
+}
+
+/// Certificate trusted for a host, as remembered by `CertificateTrustCache`.
+///
+/// Caches may not know everything about certificates trusted long ago.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PinnedCertificate {
+    pub fingerprint: Fingerprint,
+    /// Fingerprint of DER-encoded SubjectPublicKeyInfo, if known.
+    pub public_key: Option<Fingerprint>,
+    /// Names the certificate is issued for, if known.
+    pub names: Vec<String>,
+    /// Beginning of the certificate validity period, if known.
+    pub not_before: Option<SystemTime>,
+    /// End of the certificate validity period.
+    pub not_after: SystemTime,
+}
+
+impl PinnedCertificate {
+    /// Describes `certificate` with given `fingerprint`.
+    pub fn new(certificate: &X509Certificate<'_>, fingerprint: &Fingerprint) -> Self {
+        CertificateDetails::new(certificate, fingerprint).into()
+    }
+
+    /// Whether the certificate is known to be valid for the host now.
+    fn is_valid_for(&self, host: &Host) -> bool {
+        match self.not_before {
+            Some(not_before) => {
+                x509::check_names_for_host(&self.names, not_before, self.not_after, host).is_ok()
+            }
+            None => false,
+        }
+    }
+}
+
+impl From<CertificateDetails> for PinnedCertificate {
+    fn from(details: CertificateDetails) -> Self {
+        Self {
+            fingerprint: details.fingerprint,
+            public_key: details.public_key,
+            names: details.names,
+            not_before: Some(details.not_before),
+            not_after: details.not_after,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::known_hosts::KnownHosts;
+    use crate::storage::tests::TemporaryDirectory;
+    use crate::test_support::{self, certificate};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+
+    /// Asks to trust once, remembering what it's been told.
+    #[derive(Default)]
+    struct Recorder {
+        details: Mutex<Vec<CertificateDetails>>,
+        issues: Mutex<Vec<VerificationIssue>>,
+        rotations: Mutex<Vec<RotationRule>>,
+    }
+
+    impl VerificationDelegate for Recorder {
+        fn decide_certificate_trust(
+            &self,
+            _certificate: &X509Certificate<'_>,
+            details: &CertificateDetails,
+            _endpoint: &Endpoint,
+            issue: VerificationIssue,
+        ) -> TrustDecision {
+            self.details.lock().unwrap().push(details.clone());
+            self.issues.lock().unwrap().push(issue);
+            TrustDecision::TrustTemporary
+        }
+
+        fn certificate_rotated(
+            &self,
+            _certificate: &X509Certificate<'_>,
+            _endpoint: &Endpoint,
+            rule: RotationRule,
+        ) {
+            self.rotations.lock().unwrap().push(rule);
+        }
+    }
+
+    fn pin(
+        known_hosts: &KnownHosts,
+        certificate: &Certificate,
+        expired: bool,
+    ) -> PinnedCertificate {
+        let (_, parsed) = x509_parser::parse_x509_certificate(&certificate.0).unwrap();
+        let mut pinned = PinnedCertificate::new(&parsed, &Fingerprint::new(certificate));
+        if expired {
+            pinned.not_after = UNIX_EPOCH + Duration::from_secs(1);
+        }
+        known_hosts.trust_always(&host(), 1965, &pinned).unwrap();
+        pinned
+    }
+
+    fn host() -> Host {
+        Host::Domain("example.com".to_owned())
+    }
+
+    fn endpoint() -> Endpoint {
+        test_support::endpoint("gemini://example.com/page")
+    }
+
+    #[test]
+    fn rotation_rules() {
+        let directory = TemporaryDirectory::new();
+        let (old, key) = certificate(None);
+        let (same_key, _) = certificate(Some(&key));
+        let (new_key, _) = certificate(None);
+        let lenient = RotationPolicy {
+            pinned_expired: RotationAction::Prompt,
+            same_public_key: RotationAction::Accept,
+            ..RotationPolicy::default()
+        };
+        let cases = [
+            (RotationPolicy::default(), &same_key, true, None),
+            (lenient, &new_key, false, None),
+            (lenient, &new_key, true, Some(RotationRule::PinnedExpired)),
+            (lenient, &same_key, true, Some(RotationRule::SamePublicKey)),
+        ];
+        for (index, (policy, presented, expired, rule)) in cases.iter().enumerate() {
+            let known_hosts = KnownHosts::open(directory.0.join(index.to_string())).unwrap();
+            let pinned = pin(&known_hosts, &old, *expired);
+            let verifier =
+                CertificateVerifier::new(Recorder::default(), known_hosts).rotation_policy(*policy);
+            assert!(verifier
+                .verify_server_cert(std::slice::from_ref(*presented), &endpoint())
+                .is_ok());
+
+            let issues = verifier.delegate.issues.lock().unwrap();
+            let rotations = verifier.delegate.rotations.lock().unwrap();
+            let known_hosts = &verifier.trust_cache;
+            match rule {
+                None => assert_eq!(*issues, [VerificationIssue::FingerprintMismatch(pinned)]),
+                Some(RotationRule::PinnedExpired) => assert_eq!(
+                    *issues,
+                    [VerificationIssue::CertificateRotated(
+                        RotationRule::PinnedExpired,
+                        pinned
+                    )]
+                ),
+                Some(rule) => {
+                    assert!(issues.is_empty());
+                    assert_eq!(*rotations, [*rule]);
+                    let known = known_hosts.get(&host(), 1965).unwrap();
+                    assert_eq!(known.certificate.fingerprint, Fingerprint::new(*presented));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_certificates_are_not_parsed() {
+        let directory = TemporaryDirectory::new();
+        let known_hosts = KnownHosts::open(directory.0.join("known_hosts")).unwrap();
+        let garbage = Certificate(b"not a certificate".to_vec());
+        let now = SystemTime::now();
+        let mut pinned = PinnedCertificate {
+            fingerprint: Fingerprint::new(&garbage),
+            public_key: None,
+            names: vec!["*.com".to_owned()],
+            not_before: Some(now - Duration::from_secs(60)),
+            not_after: now + Duration::from_secs(60),
+        };
+        known_hosts.trust_always(&host(), 1965, &pinned).unwrap();
+        let verifier = CertificateVerifier::new(Recorder::default(), known_hosts);
+        let verify = |verifier: &CertificateVerifier<Recorder, KnownHosts>| {
+            verifier.verify_server_cert(std::slice::from_ref(&garbage), &endpoint())
+        };
+        assert!(verify(&verifier).is_ok());
+
+        // Certificates which may be no longer valid have to be parsed and checked again.
+        pinned.not_after = now - Duration::from_secs(1);
+        verifier
+            .trust_cache
+            .trust_always(&host(), 1965, &pinned)
+            .unwrap();
+        assert_eq!(
+            verify(&verifier).err(),
+            Some(TLSError::WebPKIError(webpki::Error::BadDER))
+        );
+    }
+
+    /// Hands out prompts to the test, waiting for answers.
+    struct Dialogs(Mutex<mpsc::Sender<TrustPrompt>>);
+
+    impl VerificationDelegate for Dialogs {
+        fn decide_certificate_trust(
+            &self,
+            _certificate: &X509Certificate<'_>,
+            _details: &CertificateDetails,
+            _endpoint: &Endpoint,
+            _issue: VerificationIssue,
+        ) -> TrustDecision {
+            let (decision, prompt) = PendingDecision::new();
+            self.0.lock().unwrap().send(prompt).unwrap();
+            decision.wait()
+        }
+    }
+
+    #[test]
+    fn concurrent_prompts() {
+        let directory = TemporaryDirectory::new();
+        let known_hosts = KnownHosts::open(directory.0.join("known_hosts")).unwrap();
+        let (dialogs, prompts) = mpsc::channel();
+        let verifier = Arc::new(CertificateVerifier::new(
+            Dialogs(Mutex::new(dialogs)),
+            known_hosts,
+        ));
+        let (presented, _) = certificate(None);
+        let verify = |count: usize| {
+            (0..count)
+                .map(|_| {
+                    let (verifier, presented) = (verifier.clone(), presented.clone());
+                    thread::spawn(move || {
+                        verifier
+                            .verify_server_cert(&[presented], &endpoint())
+                            .is_ok()
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let slot = |verifier: &CertificateVerifier<Dialogs, KnownHosts>| {
+            let pending = verifier.prompts.pending.lock().unwrap();
+            pending.values().next().cloned()
+        };
+
+        // Rejection is not remembered, so the next request asks again.
+        // Answer only after everyone is waiting: held by the map, the asking thread, and waiters.
+        let threads = verify(4);
+        let prompt = prompts.recv().unwrap();
+        while slot(&verifier).map_or(0, |slot| Arc::strong_count(&slot)) < 4 + 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        prompt.answer(TrustDecision::Abort);
+        for thread in threads {
+            assert!(!thread.join().unwrap());
+        }
+        assert!(prompts.try_recv().is_err());
+
+        // Dropping the prompt rejects the certificate as well.
+        let threads = verify(1);
+        drop(prompts.recv().unwrap());
+        assert!(!threads.into_iter().next().unwrap().join().unwrap());
+
+        let threads = verify(4);
+        prompts
+            .recv()
+            .unwrap()
+            .answer(TrustDecision::TrustTemporary);
+        for thread in threads {
+            assert!(thread.join().unwrap());
+        }
+        assert!(prompts.try_recv().is_err());
+        assert!(slot(&verifier).is_none());
+    }
+
+    fn authority(name: &str) -> rcgen::Certificate {
+        let mut params = rcgen::CertificateParams::new(vec![]);
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    #[test]
+    fn trusted_chain_rotation() {
+        let root = authority("Root");
+        let intermediate = authority("Intermediate");
+        let leaf = rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec![
+            "example.com".to_owned(),
+        ]))
+        .unwrap();
+        let leaf = Certificate(leaf.serialize_der_with_signer(&intermediate).unwrap());
+        let intermediate = Certificate(intermediate.serialize_der_with_signer(&root).unwrap());
+        let directory = TemporaryDirectory::new();
+        let bundle = directory.0.join("roots.pem");
+        std::fs::write(&bundle, root.serialize_pem().unwrap()).unwrap();
+        let roots = load_roots(&bundle).unwrap();
+        let policy = RotationPolicy {
+            trusted_chain: RotationAction::Accept,
+            ..RotationPolicy::default()
+        };
+
+        let (old, _) = certificate(None);
+        let chain = [leaf.clone(), intermediate];
+        let cases = [
+            (Some(roots.clone()), &chain[..], true),
+            (Some(roots), &chain[..1], false),
+            (None, &chain[..], false),
+        ];
+        for (index, (roots, presented, accepted)) in cases.iter().enumerate() {
+            let known_hosts = KnownHosts::open(directory.0.join(index.to_string())).unwrap();
+            let pinned = pin(&known_hosts, &old, false);
+            let mut verifier =
+                CertificateVerifier::new(Recorder::default(), known_hosts).rotation_policy(policy);
+            if let Some(roots) = roots {
+                verifier = verifier.certificate_authorities(roots.clone());
+            }
+            assert!(verifier.verify_server_cert(presented, &endpoint()).is_ok());
+
+            let issues = verifier.delegate.issues.lock().unwrap();
+            let rotations = verifier.delegate.rotations.lock().unwrap();
+            if *accepted {
+                assert!(issues.is_empty());
+                assert_eq!(*rotations, [RotationRule::TrustedChain]);
+                let known = verifier.trust_cache.get(&host(), 1965).unwrap();
+                assert_eq!(known.certificate.fingerprint, Fingerprint::new(&leaf));
+            } else {
+                let details = verifier.delegate.details.lock().unwrap();
+                assert!(!details[0].trusted_chain);
+                assert_eq!(*issues, [VerificationIssue::FingerprintMismatch(pinned)]);
+            }
+        }
+    }
+
+    #[test]
+    fn certificate_details() {
+        let issuer = {
+            let mut params = rcgen::CertificateParams::new(vec![]);
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(rcgen::DnType::CommonName, "Authority");
+            rcgen::Certificate::from_params(params).unwrap()
+        };
+        let mut params =
+            rcgen::CertificateParams::new(vec!["example.org".to_owned(), "127.0.0.1".to_owned()]);
+        params.subject_alt_names[1] = rcgen::SanType::IpAddress("127.0.0.1".parse().unwrap());
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Example");
+        let leaf = rcgen::Certificate::from_params(params).unwrap();
+        let issued = Certificate(leaf.serialize_der_with_signer(&issuer).unwrap());
+        let (self_signed, _) = certificate(None);
+
+        let directory = TemporaryDirectory::new();
+        let known_hosts = KnownHosts::open(directory.0.join("known_hosts")).unwrap();
+        let verifier = CertificateVerifier::new(Recorder::default(), known_hosts);
+        for certificate in &[issued, self_signed] {
+            assert!(verifier
+                .verify_server_cert(std::slice::from_ref(certificate), &endpoint())
+                .is_ok());
+        }
+        let issues = verifier.delegate.issues.lock().unwrap();
+        let details = verifier.delegate.details.lock().unwrap();
+        // The first one is trusted once despite the issue, and then the second one is different.
+        assert_eq!(
+            *issues,
+            [
+                VerificationIssue::InvalidCertificate(webpki::Error::CertNotValidForName),
+                VerificationIssue::FingerprintMismatch(details[0].clone().into()),
+            ]
+        );
+        assert_eq!(details[0].names, ["example.org", "127.0.0.1", "Example"]);
+        assert_eq!(details[0].subject, "CN=Example");
+        assert_eq!(details[0].issuer, "CN=Authority");
+        assert!(!details[0].self_signed);
+        assert!(details[1].self_signed);
+        assert_eq!(details[1].names, ["example.com", "rcgen self signed cert"]);
+    }
 }
@@ -1,20 +1,25 @@
-use webpki::DNSNameRef;
+use crate::der;
+use crate::host::Host;
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use x509_parser::{
     certificate::X509Certificate,
+    error::X509Error,
     extensions::{GeneralName, ParsedExtension},
     time::ASN1Time,
 };
 
-/// Checks that given certificate is valid for given domain.
+/// Checks that given certificate is valid for given host.
 ///
 /// # Security
 ///
 /// This function implements lightweight verification for TOFU-style trust management.
 /// If you're here to copy-paste the algorithm and you're not writing a Gemini client,
 /// please reconsider.
-pub fn check_certificate_for_domain(
+pub fn check_certificate_for_host(
     certificate: &X509Certificate<'_>,
-    dns_name: DNSNameRef<'_>,
+    host: &Host,
 ) -> Result<(), webpki::Error> {
     // Check the certificate timestamp for validity.
     let now = ASN1Time::now();
@@ -25,14 +30,113 @@ pub fn check_certificate_for_domain(
         return Err(webpki::Error::CertExpired);
     }
     // And check that the certificate is issued to the entity we expect.
-    if !certificate_matches_domain(certificate, dns_name) {
+    let matches = match host {
+        Host::Domain(domain) => certificate_matches_domain(certificate, domain),
+        Host::Ip(ip) => certificate_matches_ip(certificate, *ip),
+    };
+    if !matches {
         return Err(webpki::Error::CertNotValidForName);
     }
     Ok(())
 }
 
-fn certificate_matches_domain(certificate: &X509Certificate, dns_name: DNSNameRef<'_>) -> bool {
-    let dns_name = dns_name.into();
+/// Checks that a certificate known by its `names` and validity period is valid for given host.
+///
+/// This is the same check as `check_certificate_for_host`, for certificates which are not parsed.
+pub(crate) fn check_names_for_host(
+    names: &[String],
+    not_before: SystemTime,
+    not_after: SystemTime,
+    host: &Host,
+) -> Result<(), webpki::Error> {
+    let now = SystemTime::now();
+    if now < not_before {
+        return Err(webpki::Error::CertNotValidYet);
+    }
+    if now > not_after {
+        return Err(webpki::Error::CertExpired);
+    }
+    let matches = match host {
+        Host::Domain(domain) => names.iter().any(|name| dns_name_matches(domain, name)),
+        Host::Ip(ip) => names.iter().any(|name| name.parse::<IpAddr>() == Ok(*ip)),
+    };
+    if !matches {
+        return Err(webpki::Error::CertNotValidForName);
+    }
+    Ok(())
+}
+
+/// Converts certificate timestamp, clamping times before the epoch.
+pub(crate) fn system_time(time: ASN1Time) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(time.timestamp().max(0) as u64)
+}
+
+/// Names the certificate is issued to: DNS names and IP addresses from SANs,
+/// followed by Common Names, which may be used instead.
+pub fn certificate_names(certificate: &X509Certificate<'_>) -> Vec<String> {
+    let mut names = Vec::new();
+    for extension in certificate.extensions().values() {
+        if let ParsedExtension::SubjectAlternativeName(san) = extension.parsed_extension() {
+            for name in &san.general_names {
+                match name {
+                    GeneralName::DNSName(name) => names.push((*name).to_owned()),
+                    GeneralName::IPAddress(octets) => {
+                        if let Some(ip) = ip_address(octets) {
+                            names.push(ip.to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    for cn in certificate.subject().iter_common_name() {
+        if let Ok(name) = cn.as_str() {
+            if !names.iter().any(|other| other == name) {
+                names.push(name.to_owned());
+            }
+        }
+    }
+    names
+}
+
+fn ip_address(octets: &[u8]) -> Option<IpAddr> {
+    match octets.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(octets).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(octets).ok()?)),
+        _ => None,
+    }
+}
+
+/// Whether the certificate is issued by itself rather than by a certificate authority.
+///
+/// Signatures with algorithms unsupported by the parser are assumed to be valid.
+pub fn is_self_signed(certificate: &X509Certificate<'_>) -> bool {
+    if certificate.issuer().as_raw() != certificate.subject().as_raw() {
+        return false;
+    }
+    !matches!(
+        certificate.verify_signature(None),
+        Err(X509Error::SignatureVerificationError)
+    )
+}
+
+/// DER-encoded SubjectPublicKeyInfo of the certificate, for public key pinning.
+pub fn subject_public_key_info<'c>(certificate: &'c X509Certificate<'_>) -> Option<&'c [u8]> {
+    // x509-parser does not keep raw SPKI, so find it in TBSCertificate:
+    // optional [0] version, serial number, signature, issuer, validity, subject, and SPKI.
+    let (tbs, _) = der::element(certificate.tbs_certificate.as_ref())?;
+    let mut fields = der::contents(tbs)?;
+    if fields.first() == Some(&0xA0) {
+        fields = der::element(fields)?.1;
+    }
+    for _ in 0..5 {
+        fields = der::element(fields)?.1;
+    }
+    der::element(fields).map(|(spki, _)| spki)
+}
+
+fn certificate_matches_domain(certificate: &X509Certificate, dns_name: &str) -> bool {
     // SANs go first. That's where most modern certificates encode their domain.
     // Consider only SAN extensions which contain a DNS name. Disregard others.
     for extension in certificate.extensions().values() {
@@ -59,16 +163,46 @@ fn certificate_matches_domain(certificate: &X509Certificate, dns_name: DNSNameRe
     false
 }
 
+fn certificate_matches_ip(certificate: &X509Certificate, ip: IpAddr) -> bool {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    // IP addresses are expected to be in SANs, as raw octets.
+    for extension in certificate.extensions().values() {
+        if let ParsedExtension::SubjectAlternativeName(san) = extension.parsed_extension() {
+            for name in &san.general_names {
+                if let GeneralName::IPAddress(cert_ip) = name {
+                    if *cert_ip == &octets[..] {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+    // Some self-signed certificates put the address into the Common Name, in text form.
+    for cn in certificate.subject().iter_common_name() {
+        if let Ok(cert_name) = cn.as_str() {
+            if cert_name.parse::<IpAddr>() == Ok(ip) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
 // "webpki" crate refuses to export its DNSName matching for the sake of "security".
 // Hence, I implement ilammy's sans-bullshit DNS name matching™ here.
 // This is incomplet and incorrekt subset of RFC 6125.
-// Known issues include handling of internationalized domain names.
+// Internationalized domain names are expected to be in punycode on both sides.
 fn dns_name_matches(dns_name: &str, pattern: &str) -> bool {
     if dns_name == pattern {
         return true; // Lucky!
     }
-    debug_assert!(dns_name.is_ascii());
-    debug_assert!(pattern.is_ascii());
+    // Names are expected to be ASCII. Certificates may contain anything though.
+    if !dns_name.is_ascii() || !pattern.is_ascii() {
+        return false;
+    }
     // Skip possible trailing dots which are allowed in absolute DNS names,
     // then proceed to comparison by DNS name components, in reverse order.
     let mut dns_name_components = dns_name.trim_end_matches('.').split('.').rev();
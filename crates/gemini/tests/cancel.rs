@@ -0,0 +1,75 @@
+mod support;
+
+use cartouche_gemini::cancel::CancelToken;
+use cartouche_gemini::request::{self, Options, Request, Timeouts};
+use cartouche_gemini::response;
+use std::thread;
+use std::time::{Duration, Instant};
+use support::Server;
+
+const DELAY: Duration = Duration::from_millis(100);
+
+fn options() -> Options {
+    Options {
+        timeouts: Timeouts {
+            connect: None,
+            handshake: None,
+            first_byte: None,
+            read: None,
+        },
+        ..Options::default()
+    }
+}
+
+fn cancel_later(cancel: &CancelToken) {
+    let cancel = cancel.clone();
+    thread::spawn(move || {
+        thread::sleep(DELAY);
+        cancel.cancel();
+    });
+}
+
+#[test]
+fn cancel_handshake() {
+    let listener = support::silent_listener();
+    let url = format!(
+        "gemini://localhost:{}/",
+        listener.local_addr().unwrap().port()
+    );
+    let options = options();
+    cancel_later(&options.cancel);
+    let start = Instant::now();
+    let result = Request::perform_with_options(&url, support::client_config(), &options);
+    assert!(matches!(result, Err(request::Error::Cancelled)));
+    assert!(start.elapsed() < 10 * DELAY);
+}
+
+#[test]
+fn cancel_blocking_read() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n");
+        thread::sleep(10 * DELAY);
+    });
+    let options = options();
+    let mut response =
+        Request::perform_with_options(&server.url("/"), support::client_config(), &options)
+            .unwrap();
+    cancel_later(&options.cancel);
+    let start = Instant::now();
+    let mut buffer = [0; 16];
+    assert!(matches!(
+        response.read(&mut buffer),
+        Err(response::Error::Cancelled)
+    ));
+    assert!(start.elapsed() < 5 * DELAY);
+}
+
+#[test]
+fn cancel_before_request() {
+    let options = options();
+    options.cancel.cancel();
+    let result =
+        Request::perform_with_options("gemini://localhost:1/", support::client_config(), &options);
+    assert!(matches!(result, Err(request::Error::Cancelled)));
+}
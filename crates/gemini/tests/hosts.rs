@@ -0,0 +1,133 @@
+mod support;
+
+use cartouche_gemini::config;
+use cartouche_gemini::host::Host;
+use cartouche_gemini::known_hosts::KnownHosts;
+use cartouche_gemini::policy::{AcceptAll, FirstMatch, HostOverrides};
+use cartouche_gemini::request::{self, Request};
+use cartouche_gemini::status::Status;
+use cartouche_gemini::verify::{
+    CertificateDetails, CertificateVerifier, Endpoint, TrustDecision, VerificationDelegate,
+    VerificationIssue,
+};
+use rustls::Session;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use support::{Server, TemporaryDirectory};
+use url::Url;
+use x509_parser::certificate::X509Certificate;
+
+#[test]
+fn ip_literal_without_sni() {
+    let server =
+        Server::serve_with_config(support::server_config(&["127.0.0.1"]), |mut connection| {
+            let request = connection.read_request();
+            assert_eq!(connection.session.get_sni_hostname(), None);
+            assert!(request.starts_with("gemini://127.0.0.1:"));
+            connection.write(b"20 text/gemini\r\n# Hello\n");
+            connection.close();
+        });
+    let url = format!("gemini://127.0.0.1:{}/", server.port());
+    let mut response = Request::perform(&url, support::strict_client_config()).unwrap();
+    assert_eq!(response.status(), Status::Success);
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "# Hello\n");
+}
+
+#[test]
+fn ip_literal_not_in_certificate() {
+    let server = Server::serve(|mut connection| {
+        // The client aborts the handshake.
+        let _ = connection.session.complete_io(&mut connection.socket);
+    });
+    let url = format!("gemini://127.0.0.1:{}/", server.port());
+    let result = Request::perform(&url, support::strict_client_config());
+    assert!(matches!(result, Err(request::Error::TLS(_))));
+}
+
+#[test]
+fn domain_name_sni() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        assert_eq!(connection.session.get_sni_hostname(), Some("localhost"));
+        connection.write(b"51 Not found\r\n");
+        connection.close();
+    });
+    let response = Request::perform(&server.url("/"), support::strict_client_config()).unwrap();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+/// Trusts everything always, remembering which pages have asked for that.
+struct Prompts(Arc<Mutex<Vec<(Url, VerificationIssue)>>>);
+
+impl VerificationDelegate for Prompts {
+    fn decide_certificate_trust(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _details: &CertificateDetails,
+        endpoint: &Endpoint,
+        issue: VerificationIssue,
+    ) -> TrustDecision {
+        self.0.lock().unwrap().push((endpoint.url.clone(), issue));
+        TrustDecision::TrustAlways
+    }
+}
+
+#[test]
+fn trust_is_per_port() {
+    let directory = TemporaryDirectory::new();
+    let path = directory.0.join("known_hosts");
+    let prompts = Arc::new(Mutex::new(Vec::new()));
+    let verifier =
+        CertificateVerifier::new(Prompts(prompts.clone()), KnownHosts::open(&path).unwrap());
+    let config = config::new_shared_config(Arc::new(verifier), None);
+
+    // Servers on different ports of localhost present different certificates.
+    let mut urls = Vec::new();
+    for _ in 0..2 {
+        let server = Server::serve(|mut connection| {
+            connection.read_request();
+            connection.write(b"51 Not found\r\n");
+            connection.close();
+        });
+        let url = server.url("/page");
+        let response = Request::perform(&url, &config).unwrap();
+        assert_eq!(response.status(), Status::NotFound);
+        urls.push(Url::parse(&url).unwrap());
+    }
+
+    let prompts = prompts.lock().unwrap();
+    assert_eq!(
+        *prompts,
+        [
+            (urls[0].clone(), VerificationIssue::UnknownCertificate),
+            (urls[1].clone(), VerificationIssue::UnknownCertificate),
+        ]
+    );
+}
+
+#[test]
+fn policy_per_endpoint() {
+    let accepted = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"51 Not found\r\n");
+        connection.close();
+    });
+    let rejected = Server::serve(|mut connection| {
+        // The client aborts the handshake.
+        let _ = connection.session.complete_io(&mut connection.socket);
+    });
+    let localhost = Host::Domain("localhost".to_owned());
+    let policy = FirstMatch::new().with(HostOverrides::new().endpoint(
+        localhost,
+        accepted.port(),
+        AcceptAll,
+    ));
+    let config = config::new_shared_config(Arc::new(policy), None);
+
+    let response = Request::perform(&accepted.url("/"), &config).unwrap();
+    assert_eq!(response.status(), Status::NotFound);
+    let result = Request::perform(&rejected.url("/"), &config);
+    assert!(matches!(result, Err(request::Error::TLS(_))));
+}
@@ -0,0 +1,131 @@
+mod support;
+
+use cartouche_gemini::config;
+use cartouche_gemini::identity::{Identity, IdentityResolver};
+use cartouche_gemini::request::{self, Request, RequestBuilder};
+use cartouche_gemini::status::Status;
+use cartouche_gemini::verify::CertificateVerifier;
+use rustls::Session;
+use std::sync::Arc;
+use support::{Server, TrustEverything};
+use url::Url;
+
+fn identity() -> Arc<Identity> {
+    let (certificate, key) = support::certificate(&["client"]);
+    Arc::new(Identity::new(vec![certificate], key))
+}
+
+/// Answers 20 to clients with the expected certificate, 60 to anonymous ones.
+fn serve_authenticated(identity: &Identity, required: bool) -> Server {
+    let expected = identity.certificates().to_vec();
+    let config = support::client_auth_server_config(&expected, required);
+    Server::serve_with_config(config, move |mut connection| {
+        connection.read_request();
+        match connection.session.get_peer_certificates() {
+            Some(certificates) => {
+                assert_eq!(certificates, expected);
+                connection.write(b"20 text/gemini\r\n");
+            }
+            None => connection.write(b"60 Certificate required\r\n"),
+        }
+        connection.close();
+    })
+}
+
+#[test]
+fn anonymous_request() {
+    let identity = identity();
+    let server = serve_authenticated(&identity, false);
+    let response = Request::perform(&server.url("/"), support::client_config()).unwrap();
+    assert_eq!(response.status(), Status::ClientCertRequired);
+}
+
+#[test]
+fn request_scoped_to_identity() {
+    let identity = identity();
+    let server = serve_authenticated(&identity, true);
+    let response = RequestBuilder::new(server.url("/"))
+        .unwrap()
+        .identity(identity)
+        .perform(&support::client_config())
+        .unwrap();
+    assert_eq!(response.status(), Status::Success);
+}
+
+#[test]
+fn identity_required_by_server() {
+    let identity = identity();
+    let config = support::client_auth_server_config(identity.certificates(), true);
+    let server = Server::serve_with_config(config, |mut connection| {
+        // The server aborts the handshake.
+        let error = connection
+            .session
+            .complete_io(&mut connection.socket)
+            .unwrap_err();
+        assert!(error.to_string().contains("no certificates"));
+    });
+    let result = Request::perform(&server.url("/"), support::client_config());
+    assert!(result.is_err());
+}
+
+struct SingleIdentity(Arc<Identity>, String);
+
+impl IdentityResolver for SingleIdentity {
+    fn resolve_identity(&self, url: &Url) -> Option<Arc<Identity>> {
+        if url.path().starts_with(&self.1) {
+            Some(self.0.clone())
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn identity_from_resolver() {
+    let identity = identity();
+    let resolver = SingleIdentity(identity.clone(), "/private/".to_owned());
+    let verifier = CertificateVerifier::new(TrustEverything, TrustEverything);
+    let config = config::new_shared_config(Arc::new(verifier), Some(Arc::new(resolver)));
+
+    let server = serve_authenticated(&identity, false);
+    let response = Request::perform(&server.url("/private/page"), &config).unwrap();
+    assert_eq!(response.status(), Status::Success);
+
+    let server = serve_authenticated(&identity, false);
+    let response = Request::perform(&server.url("/public/page"), &config).unwrap();
+    assert_eq!(response.status(), Status::ClientCertRequired);
+}
+
+#[test]
+fn rejected_identities() {
+    for (header, status) in &[
+        (&b"61 Not authorized\r\n"[..], Status::NotAuthorized),
+        (b"62 Certificate expired\r\n", Status::CertNotValid),
+    ] {
+        let header: &'static [u8] = header;
+        let server = Server::serve(move |mut connection| {
+            connection.read_request();
+            connection.write(header);
+            connection.close();
+        });
+        let response = RequestBuilder::new(server.url("/"))
+            .unwrap()
+            .identity(identity())
+            .perform(&support::client_config())
+            .unwrap();
+        assert_eq!(response.status(), *status);
+        assert!(response.status().is_client_cert());
+    }
+}
+
+#[test]
+fn invalid_identity_key() {
+    let (certificate, _) = support::certificate(&["client"]);
+    let identity = Identity::new(vec![certificate], rustls::PrivateKey(vec![1, 2, 3]));
+    // Fails before connecting anywhere.
+    let result = RequestBuilder::new("gemini://localhost:1/")
+        .unwrap()
+        .identity(Arc::new(identity))
+        .perform(&support::client_config());
+    assert!(matches!(result, Err(request::Error::TLS(_))));
+}
@@ -0,0 +1,106 @@
+#![cfg(feature = "async")]
+
+mod support;
+
+use cartouche_gemini::cancel::CancelToken;
+use cartouche_gemini::nonblocking::Request;
+use cartouche_gemini::request::{self, Options, Timeouts};
+use cartouche_gemini::response::Error;
+use cartouche_gemini::status::Status;
+use std::thread;
+use std::time::Duration;
+use support::Server;
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+#[tokio::test]
+async fn complete_response() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n# Hello\n");
+        connection.close();
+    });
+    let mut response = Request::perform(&server.url("/"), support::client_config())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), Status::Success);
+    assert_eq!(response.read_to_end().await.unwrap(), b"# Hello\n");
+    assert!(response.is_complete());
+}
+
+#[tokio::test]
+async fn rustls_config() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n# Hello\n");
+        connection.close();
+    });
+    let mut response = Request::perform(&server.url("/"), support::rustls_client_config())
+        .await
+        .unwrap();
+    assert_eq!(response.read_to_end().await.unwrap(), b"# Hello\n");
+}
+
+#[tokio::test]
+async fn truncated_response() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n# Hel");
+        connection.abort();
+    });
+    let mut response = Request::perform(&server.url("/"), support::client_config())
+        .await
+        .unwrap();
+    let mut buffer = [0; 16];
+    assert_eq!(response.read(&mut buffer).await.unwrap(), 5);
+    assert!(matches!(
+        response.read(&mut buffer).await,
+        Err(Error::Truncated)
+    ));
+    assert!(!response.is_complete());
+}
+
+#[tokio::test]
+async fn read_timeout() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n");
+        thread::sleep(2 * TIMEOUT);
+    });
+    let options = Options {
+        timeouts: Timeouts {
+            read: Some(TIMEOUT),
+            ..Timeouts::default()
+        },
+        ..Options::default()
+    };
+    let mut response =
+        Request::perform_with_options(&server.url("/"), support::client_config(), &options)
+            .await
+            .unwrap();
+    let mut buffer = [0; 16];
+    assert!(matches!(
+        response.read(&mut buffer).await,
+        Err(Error::ReadTimeout)
+    ));
+}
+
+#[tokio::test]
+async fn cancel_handshake() {
+    let listener = support::silent_listener();
+    let url = format!(
+        "gemini://localhost:{}/",
+        listener.local_addr().unwrap().port()
+    );
+    let options = Options {
+        cancel: CancelToken::new(),
+        ..Options::default()
+    };
+    let cancel = options.cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(TIMEOUT).await;
+        cancel.cancel();
+    });
+    let result = Request::perform_with_options(&url, support::client_config(), &options).await;
+    assert!(matches!(result, Err(request::Error::Cancelled)));
+}
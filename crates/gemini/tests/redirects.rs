@@ -0,0 +1,45 @@
+mod support;
+
+use cartouche_gemini::redirect::RedirectKind;
+use cartouche_gemini::request::{Error, Request};
+use std::io::Read;
+use support::Server;
+
+#[test]
+fn follows_redirect() {
+    let server = Server::serve_sequence(
+        support::server_config(&["localhost"]),
+        vec![
+            Box::new(|mut connection| {
+                connection.read_request();
+                connection.write(b"31 /target#section\r\n");
+                connection.close();
+            }),
+            Box::new(|mut connection| {
+                assert!(connection.read_request().ends_with("/target"));
+                connection.write(b"20 text/gemini\r\n# Target\n");
+                connection.close();
+            }),
+        ],
+    );
+    let mut response = Request::perform(&server.url("/"), support::client_config()).unwrap();
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "# Target\n");
+    assert_eq!(response.url().as_str(), server.url("/target"));
+    let redirects = response.redirects();
+    assert_eq!(redirects.len(), 1);
+    assert_eq!(redirects[0].from.as_str(), server.url("/"));
+    assert_eq!(redirects[0].kind, RedirectKind::Permanent);
+}
+
+#[test]
+fn detects_loop_through_fragment() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"30 /#top\r\n");
+        connection.close();
+    });
+    let result = Request::perform(&server.url("/"), support::client_config());
+    assert!(matches!(result, Err(Error::RedirectLoop(url)) if url.as_str() == server.url("/")));
+}
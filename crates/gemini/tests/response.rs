@@ -0,0 +1,77 @@
+mod support;
+
+use cartouche_gemini::request::{Request, RequestBuilder};
+use cartouche_gemini::response::Error;
+use std::io::{self, Read};
+use support::Server;
+
+#[test]
+fn complete_response() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n# Hello\n");
+        connection.close();
+    });
+    let mut response = Request::perform(&server.url("/"), support::client_config()).unwrap();
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "# Hello\n");
+    assert!(response.is_complete());
+}
+
+#[test]
+fn truncated_response() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n# Hel");
+        connection.abort();
+    });
+    let mut response = Request::perform(&server.url("/"), support::client_config()).unwrap();
+    let mut body = Vec::new();
+    let error = response.read_to_end(&mut body).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    let error = error.into_inner().unwrap().downcast::<Error>().unwrap();
+    assert!(matches!(*error, Error::Truncated));
+    assert_eq!(body, b"# Hel");
+    assert!(!response.is_complete());
+}
+
+#[test]
+fn body_size_limit() {
+    let body = b"0123456789";
+    for (limit, expected) in &[(10, Ok(&body[..])), (9, Err(9))] {
+        let server = Server::serve(|mut connection| {
+            connection.read_request();
+            connection.write(b"20 text/plain\r\n0123456789");
+            connection.close();
+        });
+        let request = RequestBuilder::new(server.url("/"))
+            .unwrap()
+            .max_body_size(*limit);
+        let mut response = request.perform(&support::client_config()).unwrap();
+        let mut body = Vec::new();
+        let result = response.read_to_end(&mut body).map_err(|error| {
+            match *error.into_inner().unwrap().downcast::<Error>().unwrap() {
+                Error::BodyTooLarge(limit) => limit,
+                other => panic!("unexpected error: {}", other),
+            }
+        });
+        match expected {
+            Ok(expected) => assert_eq!(&body[..], *expected),
+            Err(limit) => assert_eq!(result.unwrap_err(), *limit),
+        }
+    }
+}
+
+#[test]
+fn rustls_config() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n# Hello\n");
+        connection.close();
+    });
+    let mut response = Request::perform(&server.url("/"), support::rustls_client_config()).unwrap();
+    let mut body = String::new();
+    response.read_to_string(&mut body).unwrap();
+    assert_eq!(body, "# Hello\n");
+}
@@ -0,0 +1,297 @@
+//! Local Gemini server for tests.
+
+#![allow(dead_code)]
+
+use cartouche_gemini::config::{self, Config};
+use cartouche_gemini::fingerprints::Fingerprint;
+use cartouche_gemini::verify::{
+    CertificateDetails, CertificateTrustCache, CertificateVerifier, Endpoint, Response,
+    TrustDecision, VerificationDelegate, VerificationIssue,
+};
+use rustls::{
+    ClientConfig, NoClientAuth, RootCertStore, ServerCertVerified, ServerCertVerifier,
+    ServerConfig, ServerSession, Session, TLSError,
+};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use webpki::DNSNameRef;
+use x509_parser::certificate::X509Certificate;
+
+pub type Handler = Box<dyn FnOnce(Connection) + Send>;
+
+pub struct Server {
+    addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Serves a single connection with `handler`, on behalf of "localhost".
+    pub fn serve<F>(handler: F) -> Self
+    where
+        F: FnOnce(Connection) + Send + 'static,
+    {
+        Self::serve_with_config(server_config(&["localhost"]), handler)
+    }
+
+    pub fn serve_with_config<F>(config: Arc<ServerConfig>, handler: F) -> Self
+    where
+        F: FnOnce(Connection) + Send + 'static,
+    {
+        Self::serve_sequence(config, vec![Box::new(handler)])
+    }
+
+    /// Serves consecutive connections with `handlers`, one connection each.
+    pub fn serve_sequence(config: Arc<ServerConfig>, handlers: Vec<Handler>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local address");
+        let thread = thread::spawn(move || {
+            for handler in handlers {
+                let (socket, _) = listener.accept().expect("accept");
+                let session = ServerSession::new(&config);
+                handler(Connection { session, socket });
+            }
+        });
+        Self {
+            addr,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("gemini://localhost:{}{}", self.port(), path)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            // Do not panic while panicking if the test has failed already.
+            if thread.join().is_err() && !thread::panicking() {
+                panic!("server thread panicked");
+            }
+        }
+    }
+}
+
+pub struct Connection {
+    pub session: ServerSession,
+    pub socket: TcpStream,
+}
+
+impl Connection {
+    /// Reads request line, without CRLF.
+    pub fn read_request(&mut self) -> String {
+        let mut request = Vec::new();
+        let mut byte = [0; 1];
+        while !request.ends_with(b"\r\n") {
+            let mut stream = rustls::Stream::new(&mut self.session, &mut self.socket);
+            match stream.read(&mut byte) {
+                Ok(0) => continue,
+                Ok(_) => request.push(byte[0]),
+                Err(err) => panic!("failed to read request: {}", err),
+            }
+        }
+        request.truncate(request.len() - 2);
+        String::from_utf8(request).expect("request must be UTF-8")
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        let mut stream = rustls::Stream::new(&mut self.session, &mut self.socket);
+        stream.write_all(data).expect("write");
+        stream.flush().expect("flush");
+    }
+
+    /// Closes the connection properly, with close_notify.
+    pub fn close(mut self) {
+        self.session.send_close_notify();
+        while self.session.wants_write() {
+            if let Err(err) = self.session.write_tls(&mut self.socket) {
+                if err.kind() != io::ErrorKind::BrokenPipe {
+                    panic!("failed to close: {}", err);
+                }
+                break;
+            }
+        }
+    }
+
+    /// Drops the connection without close_notify.
+    pub fn abort(self) {}
+}
+
+/// Self-signed certificate for given DNS names or IP addresses.
+pub fn certificate(names: &[&str]) -> (rustls::Certificate, rustls::PrivateKey) {
+    let mut params = rcgen::CertificateParams::default();
+    params.subject_alt_names = names
+        .iter()
+        .map(|name| match name.parse() {
+            Ok(ip) => rcgen::SanType::IpAddress(ip),
+            Err(_) => rcgen::SanType::DnsName(name.to_string()),
+        })
+        .collect();
+    let certificate = rcgen::Certificate::from_params(params).expect("certificate");
+    (
+        rustls::Certificate(certificate.serialize_der().expect("certificate DER")),
+        rustls::PrivateKey(certificate.serialize_private_key_der()),
+    )
+}
+
+pub fn server_config(names: &[&str]) -> Arc<ServerConfig> {
+    let (certificate, key) = certificate(names);
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(vec![certificate], key)
+        .expect("server certificate");
+    Arc::new(config)
+}
+
+/// Server configuration which asks clients for certificates signed by `client_roots`.
+///
+/// Clients without certificates are rejected if they are `required`.
+pub fn client_auth_server_config(
+    client_roots: &[rustls::Certificate],
+    required: bool,
+) -> Arc<ServerConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    for root in client_roots {
+        roots.add(root).expect("client root");
+    }
+    let verifier = if required {
+        rustls::AllowAnyAuthenticatedClient::new(roots)
+    } else {
+        rustls::AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+    };
+    let (certificate, key) = certificate(&["localhost"]);
+    let mut config = ServerConfig::new(verifier);
+    config
+        .set_single_cert(vec![certificate], key)
+        .expect("server certificate");
+    Arc::new(config)
+}
+
+/// Client configuration which trusts any certificate.
+pub fn client_config() -> Arc<Config> {
+    let verifier = CertificateVerifier::new(TrustEverything, TrustEverything);
+    config::new_shared_config(Arc::new(verifier), None)
+}
+
+pub struct TrustEverything;
+
+impl CertificateTrustCache for TrustEverything {
+    fn get_certificate_trust(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _fingerprint: &Fingerprint,
+        _endpoint: &Endpoint,
+    ) -> Response {
+        Response::TrustedCertificate
+    }
+
+    fn trust_certificate_once(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _fingerprint: &Fingerprint,
+        _endpoint: &Endpoint,
+    ) {
+    }
+
+    fn trust_certificate_always(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _fingerprint: &Fingerprint,
+        _endpoint: &Endpoint,
+    ) {
+    }
+}
+
+impl VerificationDelegate for TrustEverything {
+    fn decide_certificate_trust(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _details: &CertificateDetails,
+        _endpoint: &Endpoint,
+        _issue: VerificationIssue,
+    ) -> TrustDecision {
+        TrustDecision::TrustTemporary
+    }
+}
+
+/// Client configuration which trusts only certificates valid for the host.
+pub fn strict_client_config() -> Arc<Config> {
+    let verifier = CertificateVerifier::new(TrustValid, TrustEverything);
+    config::new_shared_config(Arc::new(verifier), None)
+}
+
+/// Delegate which rejects all certificates with issues.
+pub struct TrustValid;
+
+impl VerificationDelegate for TrustValid {
+    fn decide_certificate_trust(
+        &self,
+        _certificate: &X509Certificate<'_>,
+        _details: &CertificateDetails,
+        _endpoint: &Endpoint,
+        _issue: VerificationIssue,
+    ) -> TrustDecision {
+        TrustDecision::Abort
+    }
+}
+
+/// Listener which accepts TCP connections, but never says anything.
+pub fn silent_listener() -> TcpListener {
+    TcpListener::bind("127.0.0.1:0").expect("bind")
+}
+
+/// Plain rustls configuration, trusting any certificate of localhost.
+pub fn rustls_client_config() -> Arc<ClientConfig> {
+    let mut config = ClientConfig::new();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(AcceptLocalhost));
+    Arc::new(config)
+}
+
+struct AcceptLocalhost;
+
+impl ServerCertVerifier for AcceptLocalhost {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        dns_name: DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        assert_eq!(<&str>::from(dns_name), "localhost");
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Directory which is removed with its contents once dropped.
+pub struct TemporaryDirectory(pub PathBuf);
+
+impl TemporaryDirectory {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "cartouche-gemini-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).expect("create temporary directory");
+        Self(path)
+    }
+}
+
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
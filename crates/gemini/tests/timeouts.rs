@@ -0,0 +1,65 @@
+mod support;
+
+use cartouche_gemini::request::{self, Options, Request, Timeouts};
+use cartouche_gemini::response;
+use std::thread;
+use std::time::{Duration, Instant};
+use support::Server;
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+fn options() -> Options {
+    Options {
+        timeouts: Timeouts {
+            connect: Some(TIMEOUT),
+            handshake: Some(TIMEOUT),
+            first_byte: Some(TIMEOUT),
+            read: Some(TIMEOUT),
+        },
+        ..Options::default()
+    }
+}
+
+#[test]
+fn handshake_timeout() {
+    let listener = support::silent_listener();
+    let url = format!(
+        "gemini://localhost:{}/",
+        listener.local_addr().unwrap().port()
+    );
+    let start = Instant::now();
+    let result = Request::perform_with_options(&url, support::client_config(), &options());
+    assert!(matches!(result, Err(request::Error::HandshakeTimeout)));
+    assert!(start.elapsed() < 10 * TIMEOUT);
+}
+
+#[test]
+fn first_byte_timeout() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        thread::sleep(2 * TIMEOUT);
+    });
+    let result =
+        Request::perform_with_options(&server.url("/"), support::client_config(), &options());
+    assert!(matches!(
+        result,
+        Err(request::Error::Response(response::Error::FirstByteTimeout))
+    ));
+}
+
+#[test]
+fn read_timeout() {
+    let server = Server::serve(|mut connection| {
+        connection.read_request();
+        connection.write(b"20 text/gemini\r\n");
+        thread::sleep(2 * TIMEOUT);
+    });
+    let mut response =
+        Request::perform_with_options(&server.url("/"), support::client_config(), &options())
+            .unwrap();
+    let mut buffer = [0; 16];
+    assert!(matches!(
+        response.read(&mut buffer),
+        Err(response::Error::ReadTimeout)
+    ));
+}